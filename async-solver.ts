@@ -5,6 +5,7 @@ import type { Request, Response } from "./worker";
 export default class AsyncSolver {
     worker: Worker;
     it: AsyncIterator<Solution>;
+    aborter: AbortController;
 
     constructor(
         parts: Part[],
@@ -17,16 +18,29 @@ export default class AsyncSolver {
         });
         this.worker = worker;
 
+        // Aborted by terminate(), so pending and future next() calls finish as done.
+        const aborter = new AbortController();
+        this.aborter = aborter;
+        const cancelled = new Promise<null>((resolve) => {
+            aborter.signal.addEventListener("abort", () => {
+                resolve(null);
+            });
+        });
+
         this.it = (async function* () {
             {
-                const e = await new Promise<MessageEvent<Response>>(
-                    (resolve) => {
+                const e = await Promise.race([
+                    new Promise<MessageEvent<Response>>((resolve) => {
                         worker.addEventListener("message", function eh(e) {
                             worker.removeEventListener("message", eh);
                             resolve(e);
                         });
-                    }
-                );
+                    }),
+                    cancelled,
+                ]);
+                if (e === null) {
+                    return;
+                }
                 if (e.data.type != "ready") {
                     throw "not ready";
                 }
@@ -38,15 +52,19 @@ export default class AsyncSolver {
             } as Request);
 
             while (true) {
-                const e = await new Promise<MessageEvent<Response>>(
-                    (resolve) => {
+                const e = await Promise.race([
+                    new Promise<MessageEvent<Response>>((resolve) => {
                         worker.addEventListener("message", function eh(e) {
                             worker.removeEventListener("message", eh);
                             resolve(e);
                         });
                         worker.postMessage({ type: "next" } as Request);
-                    }
-                );
+                    }),
+                    cancelled,
+                ]);
+                if (e === null) {
+                    return;
+                }
                 if (e.data.type != "next") {
                     throw "not ready";
                 }
@@ -64,5 +82,6 @@ export default class AsyncSolver {
 
     terminate() {
         this.worker.terminate();
+        this.aborter.abort();
     }
 }
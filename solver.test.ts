@@ -0,0 +1,131 @@
+import { describe, expect, test } from "vitest";
+
+import * as array2d from "./array2d";
+import {
+    checkPlacement,
+    checkRequirements,
+    Constraint,
+    newGridSettings,
+    Part,
+    placementCount,
+    SolveError,
+    SolveInput,
+    solveInputFromJson,
+    solveInputToJson,
+} from "./solver";
+
+const ONE_CELL_PART: Part = {
+    isSolid: true,
+    color: 0,
+    compressedMask: array2d.full(true, 1, 1),
+    uncompressedMask: array2d.full(true, 1, 1),
+};
+
+const ANY_PLACEMENT: Constraint = {
+    compressed: null,
+    onCommandLine: null,
+    minBugLevel: 0,
+    maxBugLevel: Infinity,
+};
+
+const DOMINO_PART: Part = {
+    isSolid: true,
+    color: 0,
+    compressedMask: array2d.full(true, 1, 2),
+    uncompressedMask: array2d.full(true, 1, 2),
+};
+
+describe("placementCount", () => {
+    test("reaches every cell of the board, including the last row and column", () => {
+        expect(
+            placementCount(
+                ONE_CELL_PART,
+                newGridSettings(2, 2, false, 0),
+                ANY_PLACEMENT,
+                false
+            )
+        ).toBe(4);
+    });
+
+    test("doesn't rotate an unspinnable block into a disallowed rotation", () => {
+        expect(
+            placementCount(
+                DOMINO_PART,
+                newGridSettings(3, 3, false, 0),
+                { ...ANY_PLACEMENT, rotations: [1] },
+                false
+            )
+        ).toBe(0);
+    });
+});
+
+describe("solveInputToJson", () => {
+    test("round-trips unbounded bug levels and 2D arrays", () => {
+        const input: SolveInput = {
+            parts: [ONE_CELL_PART],
+            requirements: [{ partIndex: 0, constraint: ANY_PLACEMENT }],
+            gridSettings: {
+                ...newGridSettings(3, 2, false, 0),
+                colorRegions: { 0: array2d.full(true, 2, 3) },
+            },
+            spinnableColors: [false],
+        };
+        const roundTripped = solveInputFromJson(solveInputToJson(input));
+        expect(roundTripped).toEqual(input);
+        expect(roundTripped.requirements[0].constraint.maxBugLevel).toBe(
+            Infinity
+        );
+        expect(roundTripped.gridSettings.colorRegions![0].nrows).toBe(2);
+        expect(roundTripped.gridSettings.colorRegions![0].ncols).toBe(3);
+    });
+});
+
+describe("checkPlacement", () => {
+    const part: Part = {
+        isSolid: true,
+        color: 0,
+        compressedMask: array2d.full(true, 1, 1),
+        uncompressedMask: array2d.full(true, 1, 2),
+    };
+    const uncompressed = {
+        loc: { position: { x: 0, y: 0 }, rotation: 0 },
+        compressed: false,
+    };
+    const gridSettings = newGridSettings(3, 3, false, 1);
+
+    test("rejects a form the constraint doesn't allow", () => {
+        expect(
+            checkPlacement(
+                part,
+                uncompressed,
+                gridSettings,
+                { ...ANY_PLACEMENT, compressed: true },
+                true
+            )
+        ).toBe(false);
+        expect(
+            checkPlacement(
+                part,
+                uncompressed,
+                gridSettings,
+                { ...ANY_PLACEMENT, compressed: false },
+                true
+            )
+        ).toBe(true);
+    });
+});
+
+describe("checkRequirements", () => {
+    test("rejects a playable mask that isn't the size of the board", () => {
+        expect(
+            checkRequirements(
+                [ONE_CELL_PART],
+                [{ partIndex: 0, constraint: ANY_PLACEMENT }],
+                {
+                    ...newGridSettings(3, 2, false, 0),
+                    playableMask: array2d.full(true, 3, 2),
+                }
+            )
+        ).toBe(SolveError.BoardMaskShapeMismatch);
+    });
+});
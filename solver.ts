@@ -12,11 +12,15 @@ export interface Constraint {
     onCommandLine: boolean | null;
     minBugLevel: number;
     maxBugLevel: number;
+    compressedIfOnCommandLine?: boolean | null;
+    rotations?: number[] | null;
+    touchesEdge?: boolean | null;
 }
 
 export interface Requirement {
     partIndex: number;
     constraint: Constraint;
+    optional?: boolean;
 }
 
 export interface GridSettings {
@@ -24,6 +28,36 @@ export interface GridSettings {
     width: number;
     hasOob: boolean;
     commandLineRow: number;
+    forbiddenCells?: Position[];
+    positionOrder?: PositionOrder;
+    boardConstraints?: BoardConstraint[];
+    requireSolidConnected?: boolean;
+    maxDistinctColors?: number;
+    targetFill?: number;
+    // Board-sized masks of where blocks of each color may go. Colors without a mask can go anywhere.
+    colorRegions?: { [color: number]: array2d.Array2D<boolean> };
+    // A board-sized mask of the only cells blocks may go in. Everything outside of it is forbidden.
+    playableMask?: array2d.Array2D<boolean>;
+    // How many copies of each part are available. Parts without an entry are unlimited.
+    partInventory?: { [partIndex: number]: number };
+}
+
+// Constraints on the board as a whole, rather than on any single block.
+export type BoardConstraint =
+    | { type: "minColorCells"; color: number; count: number }
+    | { type: "maxColorCells"; color: number; count: number }
+    | { type: "colorsNotAdjacent"; colors: [number, number] }
+    | { type: "adjacent"; requirements: [number, number] }
+    | { type: "commandLineContiguous" };
+
+// The order in which candidate positions for a block are tried, which decides which solutions are found first.
+export enum PositionOrder {
+    // Top to bottom, then left to right. This is the default.
+    RowMajor = "rowMajor",
+    // Left to right, then top to bottom.
+    ColumnMajor = "columnMajor",
+    // Closest to the center of the grid first.
+    CenterOut = "centerOut",
 }
 
 export interface Position {
@@ -41,7 +75,37 @@ export interface Placement {
     compressed: boolean;
 }
 
-export type Solution = Placement[];
+// One placement per requirement, in the same order as the requirements. Optional requirements that were left out are null.
+export type Solution = (Placement | null)[];
+
+export function newGridSettings(
+    width: number,
+    height: number,
+    hasOob: boolean,
+    commandLineRow: number
+): GridSettings {
+    if (commandLineRow < 0 || commandLineRow >= height) {
+        throw new Error(
+            `command line row ${commandLineRow} is outside of a grid of height ${height}`
+        );
+    }
+
+    // The out of bounds corners need at least one in bounds row and column between them.
+    if (hasOob && (width < 3 || height < 3)) {
+        throw new Error(
+            `grid of size ${width}x${height} is too small to have out of bounds cells`
+        );
+    }
+
+    // With out of bounds cells, the top and bottom rows are out of bounds and start and end in forbidden corners, so the command line can't be there.
+    if (hasOob && (commandLineRow === 0 || commandLineRow === height - 1)) {
+        throw new Error(
+            `command line row ${commandLineRow} is out of bounds of a grid of height ${height}`
+        );
+    }
+
+    return { width, height, hasOob, commandLineRow };
+}
 
 enum Cell {
     Forbidden = -2,
@@ -85,7 +149,63 @@ function arrayBooleanToNumber(arr: boolean[]): number[] {
     return out;
 }
 
+export type MaskError =
+    | { type: "invalidShape"; nrows: number; ncols: number }
+    | { type: "shapeMismatch"; expected: number; got: number }
+    | { type: "empty" };
+
+export type MaskResult =
+    | { ok: true; mask: array2d.Array2D<boolean> }
+    | { ok: false; error: MaskError };
+
+// Builds a mask from untrusted data, e.g. a parts database, reporting exactly what's wrong with it instead of producing a broken mask.
+// A 0x0 shape with no cells is well-formed but still empty, so it reports empty rather than a shape mismatch.
+export function newMask(
+    cells: boolean[],
+    nrows: number,
+    ncols: number
+): MaskResult {
+    if (
+        !Number.isInteger(nrows) ||
+        !Number.isInteger(ncols) ||
+        nrows < 0 ||
+        ncols < 0
+    ) {
+        return { ok: false, error: { type: "invalidShape", nrows, ncols } };
+    }
+    if (cells.length != nrows * ncols) {
+        return {
+            ok: false,
+            error: {
+                type: "shapeMismatch",
+                expected: nrows * ncols,
+                got: cells.length,
+            },
+        };
+    }
+    if (!arrayAny(cells)) {
+        return { ok: false, error: { type: "empty" } };
+    }
+    return { ok: true, mask: array2d.from(cells, nrows, ncols) };
+}
+
+function maskCellCount(mask: array2d.Array2D<boolean>): number {
+    return arrayCountTrue(mask);
+}
+
+// The number of cells the part takes up in the given form.
+export function partArea(part: Part, compressed: boolean): number {
+    return maskCellCount(
+        compressed ? part.compressedMask : part.uncompressedMask
+    );
+}
+
 function trim(arr2d: array2d.Array2D<boolean>): array2d.Array2D<boolean> {
+    // An empty mask has no extent at all, so trim it down to nothing instead of computing a negative size.
+    if (!arrayAny(arr2d)) {
+        return array2d.full(false, 0, 0);
+    }
+
     let left = 0;
     for (; left < arr2d.ncols; ++left) {
         if (arrayAny(array2d.col(arr2d, left))) {
@@ -122,35 +242,105 @@ function trim(arr2d: array2d.Array2D<boolean>): array2d.Array2D<boolean> {
     return array2d.subarray(arr2d, top, left, nrows, ncols);
 }
 
-class Grid {
+// Compares the shapes of two masks, ignoring any empty padding around them.
+export function shapeEqual(
+    l: array2d.Array2D<boolean>,
+    r: array2d.Array2D<boolean>
+): boolean {
+    return array2d.equal(trim(l), trim(r));
+}
+
+// Whether the two masks share any filled cell when r's top left corner is at offset relative to l's, e.g. to tell up front which pairs of placements can never coexist.
+export function masksOverlapAt(
+    l: array2d.Array2D<boolean>,
+    r: array2d.Array2D<boolean>,
+    offset: Position
+): boolean {
+    const top = Math.max(0, offset.y);
+    const bottom = Math.min(l.nrows, offset.y + r.nrows);
+    const left = Math.max(0, offset.x);
+    const right = Math.min(l.ncols, offset.x + r.ncols);
+    for (let y = top; y < bottom; ++y) {
+        for (let x = left; x < right; ++x) {
+            if (
+                l[y * l.ncols + x] &&
+                r[(y - offset.y) * r.ncols + (x - offset.x)]
+            ) {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+// Why a mask can't be placed at a position.
+export enum PlaceError {
+    // Some of the mask's cells would land outside of the grid.
+    SourceClipped = "sourceClipped",
+    // Some of the mask's cells would land on forbidden cells.
+    DestinationForbidden = "destinationForbidden",
+    // Some of the mask's cells would land on cells another block already occupies.
+    DestinationOccupied = "destinationOccupied",
+}
+
+const ASCII_CELL_CHARS =
+    "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+export class Grid {
     hasOob: boolean;
     commandLineRow: number;
+    colorRegions: { [color: number]: array2d.Array2D<boolean> };
     cells: array2d.Array2D<number>;
 
     constructor(settings: GridSettings) {
         this.hasOob = settings.hasOob;
         this.commandLineRow = settings.commandLineRow;
+        this.colorRegions = settings.colorRegions || {};
         this.cells = array2d.full<number>(
             Cell.Empty,
             settings.height,
             settings.width
         );
         if (this.hasOob) {
-            this.cells[0 * settings.width + 0] = Cell.Forbidden;
-            this.cells[0 * settings.width + (settings.width - 1)] =
-                Cell.Forbidden;
-            this.cells[
-                (settings.height - 1) * settings.width + (settings.width - 1)
-            ] = Cell.Forbidden;
-            this.cells[(settings.height - 1) * settings.width + 0] =
-                Cell.Forbidden;
+            this.set(0, 0, Cell.Forbidden);
+            this.set(settings.width - 1, 0, Cell.Forbidden);
+            this.set(settings.width - 1, settings.height - 1, Cell.Forbidden);
+            this.set(0, settings.height - 1, Cell.Forbidden);
+        }
+        for (const { x, y } of settings.forbiddenCells || []) {
+            if (
+                x < 0 ||
+                x >= settings.width ||
+                y < 0 ||
+                y >= settings.height
+            ) {
+                continue;
+            }
+            this.set(x, y, Cell.Forbidden);
         }
+        if (settings.playableMask != null) {
+            for (let i = 0; i < this.cells.length; ++i) {
+                if (!settings.playableMask[i]) {
+                    this.cells[i] = Cell.Forbidden;
+                }
+            }
+        }
+    }
+
+    // Cells are stored row by row, i.e. (x, y) is cells[y * width + x]. Going through get and set keeps the axes from getting mixed up.
+    get(x: number, y: number): number {
+        return this.cells[y * this.cells.ncols + x];
+    }
+
+    set(x: number, y: number, cell: number) {
+        this.cells[y * this.cells.ncols + x] = cell;
     }
 
     clone(): Grid {
         const grid = new Grid({
             hasOob: this.hasOob,
             commandLineRow: this.commandLineRow,
+            colorRegions: this.colorRegions,
             width: 0,
             height: 0,
         });
@@ -159,6 +349,13 @@ class Grid {
     }
 
     canPlace(mask: array2d.Array2D<boolean>, pos: Position) {
+        return this.checkPlace(mask, pos) === null;
+    }
+
+    checkPlace(
+        mask: array2d.Array2D<boolean>,
+        pos: Position
+    ): PlaceError | null {
         let srcTop = 0;
         let dstTop = 0;
         if (pos.y < 0) {
@@ -175,20 +372,15 @@ class Grid {
             dstLeft = pos.x;
         }
 
-        // Check if the source mask isn't getting clipped.
+        // Check if the source mask isn't getting clipped off the top or left. Clipping off the bottom or right is caught below, which also works for masks smaller than the grid.
         for (let y = 0; y < mask.nrows; ++y) {
             for (let x = 0; x < mask.ncols; ++x) {
-                if (
-                    x >= srcLeft &&
-                    y >= srcTop &&
-                    x < mask.ncols - dstLeft &&
-                    y < mask.nrows - dstTop
-                ) {
+                if (x >= srcLeft && y >= srcTop) {
                     continue;
                 }
 
                 if (mask[y * mask.ncols + x]) {
-                    return false;
+                    return PlaceError.SourceClipped;
                 }
             }
         }
@@ -205,17 +397,20 @@ class Grid {
                 }
 
                 if (dstX >= this.cells.ncols || dstY >= this.cells.nrows) {
-                    return false;
+                    return PlaceError.SourceClipped;
                 }
 
-                const gridCellsIdx = dstY * this.cells.ncols + dstX;
-                if (this.cells[gridCellsIdx] != Cell.Empty) {
-                    return false;
+                const cell = this.get(dstX, dstY);
+                if (cell == Cell.Forbidden) {
+                    return PlaceError.DestinationForbidden;
+                }
+                if (cell != Cell.Empty) {
+                    return PlaceError.DestinationOccupied;
                 }
             }
         }
 
-        return true;
+        return null;
     }
 
     placeNoCheck(
@@ -255,9 +450,185 @@ class Grid {
                     continue;
                 }
 
-                this.cells[dstY * this.cells.ncols + dstX] = reqIdx;
+                this.set(dstX, dstY, reqIdx);
+            }
+        }
+    }
+
+    // The index of the requirement whose block covers the cell, or null if the cell is empty, forbidden or off the grid.
+    cellOwner(x: number, y: number): number | null {
+        if (x < 0 || x >= this.cells.ncols || y < 0 || y >= this.cells.nrows) {
+            return null;
+        }
+        const cell = this.get(x, y);
+        return cell >= 0 ? cell : null;
+    }
+
+    // The cells whose contents differ between the two grids, e.g. to highlight what changed between two solutions. If requirements are given, cells held by different requirements for the same part count as the same.
+    diff(other: Grid, requirements: Requirement[] | null = null): Position[] {
+        const positions: Position[] = [];
+        for (let y = 0; y < this.cells.nrows; ++y) {
+            for (let x = 0; x < this.cells.ncols; ++x) {
+                let l = this.get(x, y);
+                let r = other.get(x, y);
+                if (requirements !== null) {
+                    l = l >= 0 ? requirements[l].partIndex : l;
+                    r = r >= 0 ? requirements[r].partIndex : r;
+                }
+                if (l !== r) {
+                    positions.push({ x, y });
+                }
+            }
+        }
+        return positions;
+    }
+
+    // Takes every placed block back off the grid, leaving forbidden cells as they are, without allocating a new grid.
+    reset() {
+        for (let i = 0; i < this.cells.length; ++i) {
+            if (this.cells[i] >= 0) {
+                this.cells[i] = Cell.Empty;
+            }
+        }
+    }
+
+    // Takes a placed block back off the grid, e.g. to undo a placement in an editor without rebuilding the whole grid. Returns whether the block was on the grid at all.
+    remove(reqIdx: number): boolean {
+        if (reqIdx < 0) {
+            return false;
+        }
+        let removed = false;
+        for (let i = 0; i < this.cells.length; ++i) {
+            if (this.cells[i] === reqIdx) {
+                this.cells[i] = Cell.Empty;
+                removed = true;
+            }
+        }
+        return removed;
+    }
+
+    static fromSolution(
+        parts: Part[],
+        requirements: Requirement[],
+        solution: Solution,
+        gridSettings: GridSettings
+    ): Grid {
+        const grid = new Grid(gridSettings);
+        for (let i = 0; i < solution.length; ++i) {
+            const placement = solution[i];
+            if (placement === null) {
+                continue;
+            }
+            grid.placeNoCheck(
+                placementMask(parts[requirements[i].partIndex], placement),
+                placement.loc.position,
+                i
+            );
+        }
+        return grid;
+    }
+
+    // Renders the grid one line per row: `.` for empty cells, `#` for forbidden cells, and a digit or letter for the index of the requirement occupying the cell (`?` past the 62nd requirement).
+    toAscii(): string {
+        const lines: string[] = [];
+        for (let y = 0; y < this.cells.nrows; ++y) {
+            let line = "";
+            for (let x = 0; x < this.cells.ncols; ++x) {
+                const cell = this.get(x, y);
+                line +=
+                    cell == Cell.Empty
+                        ? "."
+                        : cell == Cell.Forbidden
+                        ? "#"
+                        : ASCII_CELL_CHARS[cell] || "?";
+            }
+            lines.push(line);
+        }
+        return lines.join("\n");
+    }
+
+    // The inverse of toAscii.
+    static fromAscii(
+        ascii: string,
+        hasOob: boolean,
+        commandLineRow: number
+    ): Grid {
+        const lines = ascii.split("\n").filter((line) => line != "");
+        const width = lines.length > 0 ? lines[0].length : 0;
+        const grid = new Grid({
+            hasOob,
+            commandLineRow,
+            width,
+            height: lines.length,
+        });
+        for (let y = 0; y < lines.length; ++y) {
+            if (lines[y].length != width) {
+                throw new Error(`row ${y} has the wrong number of cells`);
+            }
+            for (let x = 0; x < width; ++x) {
+                const c = lines[y][x];
+                let cell: number;
+                if (c == ".") {
+                    cell = Cell.Empty;
+                } else if (c == "#") {
+                    cell = Cell.Forbidden;
+                } else {
+                    cell = ASCII_CELL_CHARS.indexOf(c);
+                    if (cell == -1) {
+                        throw new Error(`unknown cell ${JSON.stringify(c)}`);
+                    }
+                }
+                grid.set(x, y, cell);
+            }
+        }
+        return grid;
+    }
+
+    // Groups the empty cells into 4-connected regions. Forbidden cells are never part of a region.
+    freeRegions(): Position[][] {
+        const regions: Position[][] = [];
+        const seen = array2d.full(false, this.cells.nrows, this.cells.ncols);
+
+        for (let y = 0; y < this.cells.nrows; ++y) {
+            for (let x = 0; x < this.cells.ncols; ++x) {
+                if (
+                    seen[y * this.cells.ncols + x] ||
+                    this.get(x, y) != Cell.Empty
+                ) {
+                    continue;
+                }
+
+                const region: Position[] = [];
+                const stack = [{ x, y }];
+                seen[y * this.cells.ncols + x] = true;
+                while (stack.length > 0) {
+                    const pos = stack.pop()!;
+                    region.push(pos);
+                    for (const [x2, y2] of [
+                        [pos.x - 1, pos.y],
+                        [pos.x + 1, pos.y],
+                        [pos.x, pos.y - 1],
+                        [pos.x, pos.y + 1],
+                    ]) {
+                        if (
+                            x2 < 0 ||
+                            x2 >= this.cells.ncols ||
+                            y2 < 0 ||
+                            y2 >= this.cells.nrows ||
+                            seen[y2 * this.cells.ncols + x2] ||
+                            this.get(x2, y2) != Cell.Empty
+                        ) {
+                            continue;
+                        }
+                        seen[y2 * this.cells.ncols + x2] = true;
+                        stack.push({ x: x2, y: y2 });
+                    }
+                }
+                regions.push(region);
             }
         }
+
+        return regions;
     }
 }
 
@@ -266,18 +637,23 @@ interface Candidate {
     mask: array2d.Array2D<boolean>;
 }
 
+// Requirements named by board constraints are told apart from other blocks of the same part, since swapping them would change which block the constraint is about.
 function partsArr2DForGrid(
     grid: Grid,
-    reqs: Requirement[]
+    reqs: Requirement[],
+    partCount: number,
+    named: Set<number>
 ): array2d.Array2D<number> {
     const partsArr2d = array2d.full(-1, grid.cells.nrows, grid.cells.ncols);
     for (let y = 0; y < grid.cells.nrows; ++y) {
         for (let x = 0; x < grid.cells.ncols; ++x) {
-            const v = grid.cells[y * partsArr2d.ncols + x];
+            const v = grid.get(x, y);
             if (v < 0) {
                 continue;
             }
-            partsArr2d[y * partsArr2d.ncols + x] = reqs[v].partIndex;
+            partsArr2d[y * partsArr2d.ncols + x] = named.has(v)
+                ? partCount + v
+                : reqs[v].partIndex;
         }
     }
     return partsArr2d;
@@ -291,34 +667,124 @@ function encodeMaskToString(mask: array2d.Array2D<boolean>): string {
     );
 }
 
-export function* solve(
-    parts: Part[],
-    requirements: Requirement[],
-    gridSettings: GridSettings,
-    spinnableColors: boolean[]
-): Iterable<Solution> {
-    if (gridSettings.commandLineRow > gridSettings.height) {
-        return;
-    }
+export interface Orientation {
+    rotation: number;
+    mask: array2d.Array2D<boolean>;
+}
 
-    // Very cheap check to see if this is even solvable at all.
-    if (!requirementsAreAdmissible(parts, requirements, gridSettings)) {
-        return;
+interface PartOrientations {
+    compressed: Orientation[];
+    uncompressed: Orientation[];
+}
+
+// Returns every way the mask can be rotated, skipping rotations that only reproduce an earlier shape.
+export function distinctOrientations(
+    mask: array2d.Array2D<boolean>
+): Orientation[] {
+    const orientations = [{ rotation: 0, mask }];
+
+    const knownMasks = new Set();
+    knownMasks.add(encodeMaskToString(trim(mask)));
+
+    for (let i = 1; i < 4; ++i) {
+        mask = array2d.rot90(mask);
+        const knownMask = encodeMaskToString(trim(mask));
+        if (knownMasks.has(knownMask)) {
+            break;
+        }
+        knownMasks.add(knownMask);
+        orientations.push({ rotation: i, mask });
     }
 
+    return orientations;
+}
+
+function orientationsForPart(part: Part): PartOrientations {
+    return {
+        compressed: distinctOrientations(part.compressedMask),
+        uncompressed: distinctOrientations(part.uncompressedMask),
+    };
+}
+
+export function distinctRotationCount(
+    part: Part,
+    compressed: boolean
+): number {
+    return distinctOrientations(
+        compressed ? part.compressedMask : part.uncompressedMask
+    ).length;
+}
+
+// How many ways a block could be placed on an otherwise empty grid under its constraint, e.g. to show how flexible a part is.
+export function placementCount(
+    part: Part,
+    gridSettings: GridSettings,
+    constraint: Constraint,
+    spinnable: boolean
+): number {
+    return candidatesForPart(
+        part,
+        orientationsForPart(part),
+        gridSettings,
+        constraint,
+        spinnable
+    ).length;
+}
+
+interface CandidatesCache {
+    partOrientations: Map<number, PartOrientations>;
+    candidates: Map<string, Candidate[]>;
+}
+
+function newCandidatesCache(): CandidatesCache {
+    return { partOrientations: new Map(), candidates: new Map() };
+}
+
+function candidatesCacheKey(req: Requirement): string {
+    const constraint = req.constraint;
+    return [
+        req.partIndex,
+        constraint.compressed,
+        constraint.onCommandLine,
+        constraint.minBugLevel,
+        constraint.maxBugLevel,
+        constraint.compressedIfOnCommandLine,
+        constraint.rotations != null ? constraint.rotations.join(",") : null,
+        constraint.touchesEdge,
+    ].join(":");
+}
+
+function sortedCandidatesForRequirements(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    spinnableColors: boolean[],
+    cache: CandidatesCache = newCandidatesCache()
+): [number, Candidate[]][] {
     const candidates = new Array<[number, Candidate[]]>(requirements.length);
     for (let i = 0; i < requirements.length; ++i) {
         const req = requirements[i];
         const part = parts[req.partIndex];
-        candidates[i] = [
-            i,
-            candidatesForPart(
+
+        const key = candidatesCacheKey(req);
+        let partCandidates = cache.candidates.get(key);
+        if (partCandidates === undefined) {
+            // Rotational symmetry only depends on the part, so work it out once per part instead of once per requirement.
+            let orientations = cache.partOrientations.get(req.partIndex);
+            if (orientations === undefined) {
+                orientations = orientationsForPart(part);
+                cache.partOrientations.set(req.partIndex, orientations);
+            }
+            partCandidates = candidatesForPart(
                 part,
+                orientations,
                 gridSettings,
                 req.constraint,
                 spinnableColors[part.color] || false
-            ),
-        ];
+            );
+            cache.candidates.set(key, partCandidates);
+        }
+        candidates[i] = [i, partCandidates];
     }
 
     // Heuristic: fit hard to fit blocks first, then easier ones.
@@ -332,441 +798,2220 @@ export function* solve(
         return i - j;
     });
 
-    const visited = new Set();
+    return candidates;
+}
 
-    for (const raw of (function* helper(
-        grid: Grid,
-        candidateIdx: number
-    ): Iterable<{ reqIdx: number; placement: Placement }[]> {
-        if (candidateIdx === candidates.length) {
-            yield [];
-            return;
-        }
+// Returns the index of the requirement with the fewest admissible placements, i.e. the one the solver will try to place first.
+export function hardestRequirement(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    spinnableColors: boolean[]
+): number | null {
+    if (
+        requirements.length === 0 ||
+        checkRequirements(parts, requirements, gridSettings) ===
+            SolveError.PartIndexOutOfRange
+    ) {
+        return null;
+    }
+    const [reqIdx] = sortedCandidatesForRequirements(
+        parts,
+        requirements,
+        gridSettings,
+        spinnableColors
+    )[0];
+    return reqIdx;
+}
 
-        const [reqIdx, cands] = candidates[candidateIdx];
-        const req = requirements[reqIdx];
-        const part = parts[req.partIndex];
+export enum SolveError {
+    PartIndexOutOfRange = "partIndexOutOfRange",
+    CommandLineRowOutOfRange = "commandLineRowOutOfRange",
+    NotEnoughParts = "notEnoughParts",
+    TooManyCommandLineParts = "tooManyCommandLineParts",
+    InsufficientArea = "insufficientArea",
+    BoardMaskShapeMismatch = "boardMaskShapeMismatch",
+}
 
-        for (const candidate of cands) {
-            if (
-                !grid.canPlace(candidate.mask, candidate.placement.loc.position)
-            ) {
-                continue;
-            }
+export type SolveResult =
+    | { ok: true; solutions: Iterable<Solution> }
+    | { ok: false; error: SolveError };
 
-            const grid2 = grid.clone();
-            grid2.placeNoCheck(
-                candidate.mask,
-                candidate.placement.loc.position,
-                reqIdx
-            );
+export function* solve(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    spinnableColors: boolean[],
+    options: SearchOptions = {}
+): Iterable<Solution> {
+    // Very cheap check to see if this is even solvable at all.
+    if (checkRequirements(parts, requirements, gridSettings) !== null) {
+        return;
+    }
 
-            if (
-                !placementIsAdmissible(
-                    grid2,
-                    part.isSolid,
-                    reqIdx,
-                    req.constraint.onCommandLine,
-                    req.constraint.maxBugLevel
-                )
-            ) {
-                continue;
-            }
+    yield* solveUnchecked(
+        parts,
+        requirements,
+        gridSettings,
+        spinnableColors,
+        newCandidatesCache(),
+        options
+    );
+}
 
-            const gridByParts = String.fromCharCode(
-                ...partsArr2DForGrid(grid2, requirements)
-            );
-            if (visited.has(gridByParts)) {
-                continue;
-            }
-            visited.add(gridByParts);
+// Everything a solve depends on, so it can be saved, e.g. to attach to a bug report, and replayed exactly.
+export interface SolveInput {
+    parts: Part[];
+    requirements: Requirement[];
+    gridSettings: GridSettings;
+    spinnableColors: boolean[];
+}
 
-            for (const solution of helper(grid2, candidateIdx + 1)) {
-                solution.push({ reqIdx, placement: candidate.placement });
-                if (
-                    candidateIdx === candidates.length - 1 &&
-                    !solutionIsAdmissible(parts, requirements, grid2)
-                ) {
-                    continue;
-                }
-                yield solution;
-            }
+function isBugLevelKey(key: string) {
+    return key === "minBugLevel" || key === "maxBugLevel";
+}
+
+// JSON drops the shape of 2D arrays, since they're just arrays with extra properties, so they're written out as objects instead. It also turns Infinity into null, so an unbounded bug level is written out as -1, like in the URL hash.
+export function solveInputToJson(input: SolveInput): string {
+    return JSON.stringify(input, (key, value) => {
+        if (isBugLevelKey(key) && value === Infinity) {
+            return -1;
         }
-    })(new Grid(gridSettings), 0)) {
-        raw.sort(({ reqIdx: i }, { reqIdx: j }) => i - j);
-        const solution = new Array(raw.length);
-        for (let i = 0; i < raw.length; ++i) {
-            solution[i] = raw[i].placement;
+        if (Array.isArray(value) && "nrows" in value) {
+            return {
+                nrows: (value as array2d.Array2D<unknown>).nrows,
+                ncols: (value as array2d.Array2D<unknown>).ncols,
+                cells: Array.from(value),
+            };
         }
-        yield solution;
-    }
+        return value;
+    });
 }
 
-function requirementsAreAdmissible(
-    parts: Part[],
-    requirements: Requirement[],
-    gridSettings: GridSettings
-) {
-    // Mandatory check: blocks required to be on the command line must be less than or equal to the number of columns.
-    let commandLineParts = 0;
-    for (const req of requirements) {
-        if (req.constraint.onCommandLine) {
-            ++commandLineParts;
+export function solveInputFromJson(json: string): SolveInput {
+    return JSON.parse(json, (key, value) => {
+        if (isBugLevelKey(key) && value === -1) {
+            return Infinity;
         }
-    }
-    if (commandLineParts > gridSettings.width) {
-        return false;
-    }
-
-    // Mandatory check: total number of squares must be less than the total allowed space.
-    let occupiedSquares = 0;
-    for (const req of requirements) {
-        const part = parts[req.partIndex];
-        occupiedSquares += arrayCountTrue(
-            req.constraint.compressed
-                ? part.compressedMask
-                : part.uncompressedMask
-        );
-    }
-    const availableSquares =
-        gridSettings.width * gridSettings.height -
-        (gridSettings.hasOob ? 4 : 0);
-    if (occupiedSquares > availableSquares) {
-        return false;
-    }
-
-    return true;
+        if (
+            value !== null &&
+            typeof value === "object" &&
+            Array.isArray(value.cells) &&
+            typeof value.nrows === "number" &&
+            typeof value.ncols === "number"
+        ) {
+            return array2d.from(value.cells, value.nrows, value.ncols);
+        }
+        return value;
+    });
 }
 
-interface PlacementDetail {
-    outOfBounds: boolean;
-    onCommandLine: boolean;
-    adjacentSameColoredPlacements: Set<number>;
+export function solveInput(input: SolveInput): Iterable<Solution> {
+    return solve(
+        input.parts,
+        input.requirements,
+        input.gridSettings,
+        input.spinnableColors
+    );
 }
 
-function resolvePlacementDetails(
+// Like solve, but tells structurally impossible inputs apart from inputs that merely have no solutions.
+export function solveChecked(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    spinnableColors: boolean[]
+): SolveResult {
+    const error = checkRequirements(parts, requirements, gridSettings);
+    if (error !== null) {
+        return { ok: false, error };
+    }
+
+    return {
+        ok: true,
+        solutions: solveUnchecked(
+            parts,
+            requirements,
+            gridSettings,
+            spinnableColors
+        ),
+    };
+}
+
+function placementsEqual(a: Placement | null, b: Placement | null) {
+    if (a === null || b === null) {
+        return a === b;
+    }
+    return (
+        a.loc.position.x === b.loc.position.x &&
+        a.loc.position.y === b.loc.position.y &&
+        a.loc.rotation === b.loc.rotation &&
+        a.compressed === b.compressed
+    );
+}
+
+// A string that is the same for two solutions exactly when they place every requirement the same way, so solutions from several solves can be deduplicated with a Set or Map.
+export function solutionKey(solution: Solution): string {
+    return solution
+        .map((placement) =>
+            placement === null
+                ? "-"
+                : `${placement.loc.position.x},${placement.loc.position.y},${
+                      placement.loc.rotation
+                  },${+placement.compressed}`
+        )
+        .join(";");
+}
+
+// Counts the requirements that are placed differently in the two solutions.
+export function diffSolutions(a: Solution, b: Solution): number {
+    let n = Math.abs(a.length - b.length);
+    const length = Math.min(a.length, b.length);
+    for (let i = 0; i < length; ++i) {
+        if (!placementsEqual(a[i], b[i])) {
+            ++n;
+        }
+    }
+    return n;
+}
+
+// Like solve, but only yields solutions that differ from the reference in at least minDiff placements.
+export function* solveDiverse(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    spinnableColors: boolean[],
+    reference: Solution,
+    minDiff: number
+): Iterable<Solution> {
+    for (const solution of solve(
+        parts,
+        requirements,
+        gridSettings,
+        spinnableColors
+    )) {
+        if (diffSolutions(solution, reference) < minDiff) {
+            continue;
+        }
+        yield solution;
+    }
+}
+
+// Finds any one solution, or null if there are none.
+//
+// A greedy pass that puts each block in the first place it fits often finds one straight away. Only if that runs into a dead end does this fall back to the full search.
+export function solveAny(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    spinnableColors: boolean[]
+): Solution | null {
+    if (checkRequirements(parts, requirements, gridSettings) !== null) {
+        return null;
+    }
+
+    const cache = newCandidatesCache();
+    const greedy = greedySolution(
+        parts,
+        requirements,
+        gridSettings,
+        spinnableColors,
+        cache
+    );
+    if (greedy !== null) {
+        return greedy;
+    }
+
+    for (const solution of solveUnchecked(
+        parts,
+        requirements,
+        gridSettings,
+        spinnableColors,
+        cache
+    )) {
+        return solution;
+    }
+    return null;
+}
+
+function greedySolution(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    spinnableColors: boolean[],
+    cache: CandidatesCache
+): Solution | null {
+    let grid = new Grid(gridSettings);
+    const solution: Solution = new Array(requirements.length).fill(null);
+    const placedCandidates: (Candidate | null)[] = new Array(
+        requirements.length
+    ).fill(null);
+
+    for (const [reqIdx, cands] of sortedCandidatesForRequirements(
+        parts,
+        requirements,
+        gridSettings,
+        spinnableColors,
+        cache
+    )) {
+        const req = requirements[reqIdx];
+        const part = parts[req.partIndex];
+
+        let placed = false;
+        for (const candidate of cands) {
+            if (
+                !grid.canPlace(candidate.mask, candidate.placement.loc.position)
+            ) {
+                continue;
+            }
+
+            const grid2 = grid.clone();
+            grid2.placeNoCheck(
+                candidate.mask,
+                candidate.placement.loc.position,
+                reqIdx
+            );
+            placedCandidates[reqIdx] = candidate;
+            if (
+                !placementIsAdmissible(grid2, part, reqIdx, req.constraint) ||
+                !partialSolutionIsAdmissible(
+                    parts,
+                    requirements,
+                    grid2,
+                    placedCandidates,
+                    reqIdx
+                )
+            ) {
+                placedCandidates[reqIdx] = null;
+                continue;
+            }
+
+            grid = grid2;
+            solution[reqIdx] = candidate.placement;
+            placed = true;
+            break;
+        }
+
+        if (!placed && !req.optional) {
+            return null;
+        }
+    }
+
+    if (!solutionIsAdmissible(parts, requirements, gridSettings, grid)) {
+        return null;
+    }
+    canonicalizeSolution(requirements, gridSettings, solution);
+    return solution;
+}
+
+// Keeps the placements enumerated for each (part, constraint) pair around, so re-solving after tweaking a few requirements only enumerates placements for the ones that changed.
+export class Solver {
+    parts: Part[];
+    gridSettings: GridSettings;
+    spinnableColors: boolean[];
+    cache: CandidatesCache;
+
+    constructor(
+        parts: Part[],
+        gridSettings: GridSettings,
+        spinnableColors: boolean[]
+    ) {
+        this.parts = parts;
+        this.gridSettings = gridSettings;
+        this.spinnableColors = spinnableColors;
+        this.cache = newCandidatesCache();
+    }
+
+    *solve(requirements: Requirement[]): Iterable<Solution> {
+        if (
+            checkRequirements(this.parts, requirements, this.gridSettings) !==
+            null
+        ) {
+            return;
+        }
+
+        yield* solveUnchecked(
+            this.parts,
+            requirements,
+            this.gridSettings,
+            this.spinnableColors,
+            this.cache
+        );
+    }
+}
+
+// Extension point for game-specific rules: called for every placement that passes the built-in admissibility checks, with the grid the block has just been placed into.
+export interface PlacementValidator {
+    validate(grid: Grid, placement: Placement, reqIdx: number): boolean;
+}
+
+// Extension point for whole-solution rules: called with every complete solution and its grid of requirement indices before it is yielded.
+export interface SolutionPostProcessor {
+    accept(solution: Solution, cells: (number | null)[]): boolean;
+}
+
+export interface SolverProgress {
+    placementsTried: number;
+    branchesPruned: number;
+    solutionsFound: number;
+}
+
+// How many placements are tried between progress reports.
+const PROGRESS_INTERVAL = 10000;
+
+export type TraceEvent =
+    | { type: "partial"; grid: Grid }
+    | { type: "complete"; solution: Solution };
+
+export interface SolverStats {
+    placementsTried: number;
+    gridClones: number;
+    visitedHits: number;
+    visitedMisses: number;
+    solutionsFound: number;
+}
+
+export function newSolverStats(): SolverStats {
+    return {
+        placementsTried: 0,
+        gridClones: 0,
+        visitedHits: 0,
+        visitedMisses: 0,
+        solutionsFound: 0,
+    };
+}
+
+// Optional tweaks to how solve searches. Any of them can be combined.
+export interface SearchOptions {
+    // Gives up after trying this many placements. Every solution yielded before the budget runs out is still a valid solution, there just may be more that were never reached.
+    maxSteps?: number;
+    validator?: PlacementValidator;
+    postProcessor?: SolutionPostProcessor;
+    // Periodically reports how much of the search space has been explored, and once more when the search is exhausted.
+    onProgress?: (progress: SolverProgress) => void;
+    // Counts what the search does as it goes, e.g. to compare solver changes.
+    stats?: SolverStats;
+    // Starts from a grid that already has some blocks in it, e.g. ones the user has locked in place, and only places the given requirements around them. The blocks already in the grid only take up space: they don't count towards colors, bugs or board constraints.
+    initialGrid?: Grid;
+}
+
+interface SearchUncheckedOptions extends SearchOptions {
+    trace?: boolean;
+}
+
+// Like solve, but also yields the partial grid every time the search commits to placing a block, e.g. to watch the solver work.
+export function* solveTrace(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    spinnableColors: boolean[],
+    options: SearchOptions = {}
+): Iterable<TraceEvent> {
+    if (checkRequirements(parts, requirements, gridSettings) !== null) {
+        return;
+    }
+
+    yield* searchUnchecked(
+        parts,
+        requirements,
+        gridSettings,
+        spinnableColors,
+        newCandidatesCache(),
+        { ...options, trace: true }
+    );
+}
+
+function* solveUnchecked(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    spinnableColors: boolean[],
+    cache: CandidatesCache = newCandidatesCache(),
+    options: SearchOptions = {}
+): Iterable<Solution> {
+    for (const event of searchUnchecked(
+        parts,
+        requirements,
+        gridSettings,
+        spinnableColors,
+        cache,
+        options
+    )) {
+        if (event.type === "complete") {
+            yield event.solution;
+        }
+    }
+}
+
+function* searchUnchecked(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    spinnableColors: boolean[],
+    cache: CandidatesCache,
+    options: SearchUncheckedOptions
+): Iterable<TraceEvent> {
+    const candidates = sortedCandidatesForRequirements(
+        parts,
+        requirements,
+        gridSettings,
+        spinnableColors,
+        cache
+    );
+
+    const visited = new Set();
+    const named = requirementsNamedByBoardConstraints(gridSettings);
+    // The candidate each block currently on the grid was placed with, for checking bug levels without rescanning the grid.
+    const placedCandidates: (Candidate | null)[] = new Array(
+        requirements.length
+    ).fill(null);
+    const stats =
+        options.stats !== undefined ? options.stats : newSolverStats();
+
+    const reportProgress = () => {
+        if (options.onProgress === undefined) {
+            return;
+        }
+        options.onProgress({
+            placementsTried: stats.placementsTried,
+            // Every placement that doesn't lead to a new grid to search from is a pruned branch.
+            branchesPruned: stats.placementsTried - stats.visitedMisses,
+            solutionsFound: stats.solutionsFound,
+        });
+    };
+
+    // If any required block can't be placed anywhere on its own, there's no point in searching.
+    if (
+        candidates.some(
+            ([reqIdx, cands]) =>
+                cands.length === 0 && !requirements[reqIdx].optional
+        )
+    ) {
+        reportProgress();
+        return;
+    }
+
+    // Cells already taken in the initial grid are off limits to every block.
+    const startGrid = new Grid(gridSettings);
+    if (options.initialGrid !== undefined) {
+        for (let i = 0; i < startGrid.cells.length; ++i) {
+            if (options.initialGrid.cells[i] !== Cell.Empty) {
+                startGrid.cells[i] = Cell.Forbidden;
+            }
+        }
+    }
+
+    for (const raw of (function* helper(
+        grid: Grid,
+        candidateIdx: number,
+        skipped: string[]
+    ): Iterable<{ reqIdx: number; placement: Placement }[] | Grid> {
+        if (candidateIdx === candidates.length) {
+            if (
+                !solutionIsAdmissible(parts, requirements, gridSettings, grid)
+            ) {
+                return;
+            }
+            yield [];
+            return;
+        }
+
+        const [reqIdx, cands] = candidates[candidateIdx];
+        const req = requirements[reqIdx];
+        const part = parts[req.partIndex];
+
+        for (const candidate of cands) {
+            if (
+                options.maxSteps !== undefined &&
+                stats.placementsTried >= options.maxSteps
+            ) {
+                return;
+            }
+            ++stats.placementsTried;
+            if (stats.placementsTried % PROGRESS_INTERVAL === 0) {
+                reportProgress();
+            }
+
+            if (
+                !grid.canPlace(candidate.mask, candidate.placement.loc.position)
+            ) {
+                continue;
+            }
+
+            const grid2 = grid.clone();
+            ++stats.gridClones;
+            grid2.placeNoCheck(
+                candidate.mask,
+                candidate.placement.loc.position,
+                reqIdx
+            );
+
+            if (
+                !placementIsAdmissible(grid2, part, reqIdx, req.constraint)
+            ) {
+                continue;
+            }
+
+            placedCandidates[reqIdx] = candidate;
+            if (
+                !partialSolutionIsAdmissible(
+                    parts,
+                    requirements,
+                    grid2,
+                    placedCandidates,
+                    reqIdx
+                )
+            ) {
+                continue;
+            }
+
+            if (
+                options.validator !== undefined &&
+                !options.validator.validate(grid2, candidate.placement, reqIdx)
+            ) {
+                continue;
+            }
+
+            // The same grid of parts can be reached with different optional blocks left out, which leaves different blocks still to place, so those are kept apart.
+            const gridByParts = String.fromCharCode(
+                ...partsArr2DForGrid(grid2, requirements, parts.length, named)
+            );
+            const visitedKey = `${candidateIdx}:${skipped.join("|")}:${gridByParts}`;
+            if (visited.has(visitedKey)) {
+                ++stats.visitedHits;
+                continue;
+            }
+            visited.add(visitedKey);
+            ++stats.visitedMisses;
+
+            if (options.trace) {
+                yield grid2;
+            }
+
+            for (const solution of helper(grid2, candidateIdx + 1, skipped)) {
+                // Partial grids from deeper down are just passed along.
+                if (solution instanceof Grid) {
+                    yield solution;
+                    continue;
+                }
+                solution.push({ reqIdx, placement: candidate.placement });
+                yield solution;
+            }
+        }
+        placedCandidates[reqIdx] = null;
+
+        // Optional blocks may also be left out entirely. This is tried last, so solutions that place more blocks come first.
+        // Only which interchangeable blocks were left out matters, not which particular ones.
+        if (req.optional) {
+            yield* helper(
+                grid,
+                candidateIdx + 1,
+                [...skipped, interchangeableKey(req, reqIdx, named)].sort()
+            );
+        }
+    })(startGrid, 0, [])) {
+        if (raw instanceof Grid) {
+            yield { type: "partial", grid: raw };
+            continue;
+        }
+
+        const solution: Solution = new Array(requirements.length).fill(null);
+        for (const { reqIdx, placement } of raw) {
+            solution[reqIdx] = placement;
+        }
+        canonicalizeSolution(requirements, gridSettings, solution);
+        if (
+            options.postProcessor !== undefined &&
+            !options.postProcessor.accept(
+                solution,
+                placeAll(parts, requirements, solution, gridSettings)
+            )
+        ) {
+            continue;
+        }
+        ++stats.solutionsFound;
+        yield { type: "complete", solution };
+    }
+    reportProgress();
+}
+
+// The requirements that board constraints refer to by index.
+function requirementsNamedByBoardConstraints(
+    gridSettings: GridSettings
+): Set<number> {
+    const named = new Set<number>();
+    for (const constraint of gridSettings.boardConstraints || []) {
+        if (constraint.type === "adjacent") {
+            for (const reqIdx of constraint.requirements) {
+                named.add(reqIdx);
+            }
+        }
+    }
+    return named;
+}
+
+// Requirements with the same key can trade placements without changing what the solution means. Requirements named by board constraints can't trade with anything, so they get a key of their own.
+function interchangeableKey(
+    req: Requirement,
+    reqIdx: number,
+    named: Set<number>
+): string {
+    if (named.has(reqIdx)) {
+        return `#${reqIdx}`;
+    }
+    return `${candidatesCacheKey(req)}:${!!req.optional}`;
+}
+
+function comparePlacements(a: Placement | null, b: Placement | null) {
+    if (a === null || b === null) {
+        return +(a === null) - +(b === null);
+    }
+    return (
+        a.loc.position.y - b.loc.position.y ||
+        a.loc.position.x - b.loc.position.x ||
+        a.loc.rotation - b.loc.rotation ||
+        +b.compressed - +a.compressed
+    );
+}
+
+// Requirements for the same part with the same constraint are interchangeable, so hand out their placements in a fixed order. Otherwise the same arrangement can come out with its interchangeable blocks in any order.
+function canonicalizeSolution(
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    solution: Solution
+) {
+    const named = requirementsNamedByBoardConstraints(gridSettings);
+    const groups = new Map<string, number[]>();
+    for (let i = 0; i < requirements.length; ++i) {
+        const key = interchangeableKey(requirements[i], i, named);
+        let group = groups.get(key);
+        if (group === undefined) {
+            group = [];
+            groups.set(key, group);
+        }
+        group.push(i);
+    }
+
+    for (const group of groups.values()) {
+        if (group.length < 2) {
+            continue;
+        }
+        const placements = group.map((reqIdx) => solution[reqIdx]);
+        placements.sort(comparePlacements);
+        for (let i = 0; i < group.length; ++i) {
+            solution[group[i]] = placements[i];
+        }
+    }
+}
+
+// The fewest cells the mask has in any non-empty row or column, i.e. the fewest cells it can put on any one row in any rotation.
+function thinnestLineCellCount(mask: array2d.Array2D<boolean>): number {
+    let n = Infinity;
+    for (let y = 0; y < mask.nrows; ++y) {
+        const count = arrayCountTrue(array2d.row(mask, y));
+        if (count > 0) {
+            n = Math.min(n, count);
+        }
+    }
+    for (let x = 0; x < mask.ncols; ++x) {
+        const count = arrayCountTrue(array2d.col(mask, x));
+        if (count > 0) {
+            n = Math.min(n, count);
+        }
+    }
+    return n === Infinity ? 0 : n;
+}
+
+// Quickly checks whether the requirements are structurally impossible, without starting a search. Returns null if they might be solvable.
+export function checkRequirements(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings
+): SolveError | null {
+    // Mandatory check: every requirement must refer to an actual part, otherwise every other check will blow up.
+    for (const req of requirements) {
+        if (
+            !Number.isInteger(req.partIndex) ||
+            req.partIndex < 0 ||
+            req.partIndex >= parts.length
+        ) {
+            return SolveError.PartIndexOutOfRange;
+        }
+    }
+
+    // Mandatory check: no part can be asked for more times than there are copies of it, even optionally.
+    if (gridSettings.partInventory !== undefined) {
+        const partCounts = new Map<number, number>();
+        for (const req of requirements) {
+            partCounts.set(
+                req.partIndex,
+                (partCounts.get(req.partIndex) || 0) + 1
+            );
+        }
+        for (const [partIndex, count] of partCounts) {
+            const available = gridSettings.partInventory[partIndex];
+            if (available !== undefined && count > available) {
+                return SolveError.NotEnoughParts;
+            }
+        }
+    }
+
+    // The command line must be on the grid, and with out of bounds cells it must be on one of the in bounds rows.
+    const minCommandLineRow = gridSettings.hasOob ? 1 : 0;
+    const maxCommandLineRow = gridSettings.hasOob
+        ? gridSettings.height - 1
+        : gridSettings.height;
+    if (
+        gridSettings.commandLineRow < minCommandLineRow ||
+        gridSettings.commandLineRow >= maxCommandLineRow
+    ) {
+        return SolveError.CommandLineRowOutOfRange;
+    }
+
+    // Board-sized masks are indexed cell by cell, so one of the wrong shape would silently cover the wrong cells.
+    const colorRegions = gridSettings.colorRegions || {};
+    const boardMasks = Object.keys(colorRegions).map(
+        (color) => colorRegions[+color]
+    );
+    if (gridSettings.playableMask != null) {
+        boardMasks.push(gridSettings.playableMask);
+    }
+    for (const mask of boardMasks) {
+        if (
+            mask.nrows !== gridSettings.height ||
+            mask.ncols !== gridSettings.width
+        ) {
+            return SolveError.BoardMaskShapeMismatch;
+        }
+    }
+
+    const emptyGrid = new Grid(gridSettings);
+
+    // Mandatory check: blocks required to be on the command line must fit on the command line together. Each one takes up at least as many command line cells as its thinnest row in any rotation.
+    // Optional blocks can always be left out, so they don't count towards either check.
+    let commandLineSquares = 0;
+    for (const req of requirements) {
+        if (!req.constraint.onCommandLine || req.optional) {
+            continue;
+        }
+        const part = parts[req.partIndex];
+        const masks =
+            req.constraint.compressed === true
+                ? [part.compressedMask]
+                : req.constraint.compressed === false
+                ? [part.uncompressedMask]
+                : [part.compressedMask, part.uncompressedMask];
+        commandLineSquares += Math.min(...masks.map(thinnestLineCellCount));
+    }
+    const availableCommandLineSquares = arrayCountNumber(
+        array2d.row(emptyGrid.cells, gridSettings.commandLineRow),
+        Cell.Empty
+    );
+    if (commandLineSquares > availableCommandLineSquares) {
+        return SolveError.TooManyCommandLineParts;
+    }
+
+    // Mandatory check: total number of squares must be less than the total allowed space.
+    let occupiedSquares = 0;
+    for (const req of requirements) {
+        if (req.optional) {
+            continue;
+        }
+        const part = parts[req.partIndex];
+        // If the form isn't pinned down, the block takes up at least as much space as its smaller form.
+        occupiedSquares +=
+            req.constraint.compressed === true
+                ? partArea(part, true)
+                : req.constraint.compressed === false
+                ? partArea(part, false)
+                : Math.min(partArea(part, true), partArea(part, false));
+    }
+    const availableSquares = arrayCountNumber(emptyGrid.cells, Cell.Empty);
+    if (occupiedSquares > availableSquares) {
+        return SolveError.InsufficientArea;
+    }
+
+    return null;
+}
+
+interface PlacementDetail {
+    placed: boolean;
+    outOfBounds: boolean;
+    onCommandLine: boolean;
+    adjacentSameColoredPlacements: Set<number>;
+}
+
+function newPlacementDetail(): PlacementDetail {
+    return {
+        placed: false,
+        outOfBounds: false,
+        onCommandLine: false,
+        adjacentSameColoredPlacements: new Set(),
+    };
+}
+
+// Accounts for one cell of a placed block in the block's placement detail.
+function addCellToPlacementDetail(
+    parts: Part[],
+    requirements: Requirement[],
+    grid: Grid,
+    x: number,
+    y: number,
+    placementDetail: PlacementDetail
+) {
+    const reqIdx = grid.get(x, y);
+    const req = requirements[reqIdx];
+    const part = parts[req.partIndex];
+
+    placementDetail.placed = true;
+
+    // Optional admissibility: check if a block has/doesn't have any out of bounds parts.
+    if (
+        grid.hasOob &&
+        (x === 0 ||
+            y === 0 ||
+            x === grid.cells.ncols - 1 ||
+            y === grid.cells.nrows - 1)
+    ) {
+        placementDetail.outOfBounds = true;
+    }
+
+    // Optional admissibility: check if a block is/isn't on the command line.
+    if (y === grid.commandLineRow) {
+        placementDetail.onCommandLine = true;
+    }
+
+    // Optional admissibility: check if same-colored blocks are appropriately touching/not touching.
+    for (const [x2, y2] of [
+        [x - 1, y],
+        [x + 1, y],
+        [x, y - 1],
+        [x, y + 1],
+    ]) {
+        if (
+            x2 < 0 ||
+            x2 >= grid.cells.ncols ||
+            y2 < 0 ||
+            y2 >= grid.cells.nrows
+        ) {
+            continue;
+        }
+
+        // Ignore touching in out of bounds regions.
+        if (
+            grid.hasOob &&
+            (x == 0 ||
+                y == 0 ||
+                x == grid.cells.ncols - 1 ||
+                y == grid.cells.nrows - 1) &&
+            (x2 == 0 ||
+                y2 == 0 ||
+                x2 == grid.cells.ncols - 1 ||
+                y2 == grid.cells.nrows - 1)
+        ) {
+            continue;
+        }
+
+        const neigborReqIdx = grid.get(x2, y2);
+        if (neigborReqIdx < 0) {
+            continue;
+        }
+
+        const neigborReq = requirements[neigborReqIdx];
+        const neighborPart = parts[neigborReq.partIndex];
+
+        if (neigborReqIdx != reqIdx && neighborPart.color === part.color) {
+            placementDetail.adjacentSameColoredPlacements.add(neigborReqIdx);
+            break;
+        }
+    }
+}
+
+// What board constraints need to know about the grid as a whole.
+interface BoardDetail {
+    filledCells: number;
+    colorCells: Map<number, number>;
+    adjacentColors: Set<string>;
+    adjacentRequirements: Set<string>;
+}
+
+function resolvePlacementDetails(
+    parts: Part[],
+    requirements: Requirement[],
+    grid: Grid
+) {
+    const placementDetails: PlacementDetail[] = new Array(requirements.length);
+    for (let i = 0; i < requirements.length; ++i) {
+        placementDetails[i] = newPlacementDetail();
+    }
+    const boardDetail: BoardDetail = {
+        filledCells: 0,
+        colorCells: new Map(),
+        adjacentColors: new Set(),
+        adjacentRequirements: new Set(),
+    };
+
+    for (let y = 0; y < grid.cells.nrows; ++y) {
+        for (let x = 0; x < grid.cells.ncols; ++x) {
+            const reqIdx = grid.get(x, y);
+            if (reqIdx < 0) {
+                continue;
+            }
+            addCellToPlacementDetail(
+                parts,
+                requirements,
+                grid,
+                x,
+                y,
+                placementDetails[reqIdx]
+            );
+
+            ++boardDetail.filledCells;
+            const color = parts[requirements[reqIdx].partIndex].color;
+            boardDetail.colorCells.set(
+                color,
+                (boardDetail.colorCells.get(color) || 0) + 1
+            );
+
+            // Only look right and down: the other two directions are covered by the neighbor.
+            for (const [x2, y2] of [
+                [x + 1, y],
+                [x, y + 1],
+            ]) {
+                if (x2 >= grid.cells.ncols || y2 >= grid.cells.nrows) {
+                    continue;
+                }
+                const neighborReqIdx = grid.get(x2, y2);
+                if (neighborReqIdx < 0 || neighborReqIdx === reqIdx) {
+                    continue;
+                }
+                const neighborColor =
+                    parts[requirements[neighborReqIdx].partIndex].color;
+                boardDetail.adjacentColors.add(`${color}:${neighborColor}`);
+                boardDetail.adjacentColors.add(`${neighborColor}:${color}`);
+                boardDetail.adjacentRequirements.add(
+                    `${reqIdx}:${neighborReqIdx}`
+                );
+                boardDetail.adjacentRequirements.add(
+                    `${neighborReqIdx}:${reqIdx}`
+                );
+            }
+        }
+    }
+
+    return { placementDetails, boardDetail };
+}
+
+// The grid positions of the cells a placed block covers.
+function candidateCells(candidate: Candidate): Position[] {
+    const { mask } = candidate;
+    const { position } = candidate.placement.loc;
+    const cells: Position[] = [];
+    for (let y = 0; y < mask.nrows; ++y) {
+        for (let x = 0; x < mask.ncols; ++x) {
+            if (mask[y * mask.ncols + x]) {
+                cells.push({ x: position.x + x, y: position.y + y });
+            }
+        }
+    }
+    return cells;
+}
+
+// Like resolvePlacementDetails, but for a single placed block, only looking at the cells it covers.
+function placementDetailForCandidate(
+    parts: Part[],
+    requirements: Requirement[],
+    grid: Grid,
+    candidate: Candidate
+) {
+    const placementDetail = newPlacementDetail();
+    for (const { x, y } of candidateCells(candidate)) {
+        addCellToPlacementDetail(
+            parts,
+            requirements,
+            grid,
+            x,
+            y,
+            placementDetail
+        );
+    }
+    return placementDetail;
+}
+
+function bugLevelForPlacementDetail(
+    placementDetail: PlacementDetail,
+    isSolid: boolean
+) {
+    return (
+        +placementDetail.outOfBounds +
+        +(isSolid === !placementDetail.onCommandLine) +
+        placementDetail.adjacentSameColoredPlacements.size
+    );
+}
+
+// Checks that the block just placed as reqIdx hasn't pushed any block past its maximum bug level. placedCandidates holds the candidate each block on the grid was placed with.
+//
+// Only the new block and the same-colored blocks it touches can have gained bugs, so only those are looked at, and only at the cells they cover.
+function partialSolutionIsAdmissible(
+    parts: Part[],
+    requirements: Requirement[],
+    grid: Grid,
+    placedCandidates: (Candidate | null)[],
+    reqIdx: number
+) {
+    const candidate = placedCandidates[reqIdx]!;
+    const color = parts[requirements[reqIdx].partIndex].color;
+
+    const toCheck = new Set([reqIdx]);
+    for (const { x, y } of candidateCells(candidate)) {
+        for (const [x2, y2] of [
+            [x - 1, y],
+            [x + 1, y],
+            [x, y - 1],
+            [x, y + 1],
+        ]) {
+            if (
+                x2 < 0 ||
+                x2 >= grid.cells.ncols ||
+                y2 < 0 ||
+                y2 >= grid.cells.nrows
+            ) {
+                continue;
+            }
+            const neighborReqIdx = grid.get(x2, y2);
+            if (
+                neighborReqIdx >= 0 &&
+                parts[requirements[neighborReqIdx].partIndex].color === color
+            ) {
+                toCheck.add(neighborReqIdx);
+            }
+        }
+    }
+
+    for (const i of toCheck) {
+        const req = requirements[i];
+        const part = parts[req.partIndex];
+        const placementDetail = placementDetailForCandidate(
+            parts,
+            requirements,
+            grid,
+            placedCandidates[i]!
+        );
+
+        // Placing more blocks can only ever add same-colored neighbors, so a placed block that already has too many bugs can never recover.
+        if (
+            bugLevelForPlacementDetail(placementDetail, part.isSolid) >
+            req.constraint.maxBugLevel
+        ) {
+            return false;
+        }
+    }
+
+    return true;
+}
+
+function boardIsAdmissible(
+    gridSettings: GridSettings,
+    grid: Grid,
+    boardDetail: BoardDetail
+) {
+    const { filledCells, colorCells, adjacentColors, adjacentRequirements } =
+        boardDetail;
+
+    if (
+        gridSettings.maxDistinctColors !== undefined &&
+        colorCells.size > gridSettings.maxDistinctColors
+    ) {
+        return false;
+    }
+
+    if (
+        gridSettings.targetFill !== undefined &&
+        filledCells !== gridSettings.targetFill
+    ) {
+        return false;
+    }
+
+    for (const constraint of gridSettings.boardConstraints || []) {
+        switch (constraint.type) {
+            case "minColorCells":
+                if (
+                    (colorCells.get(constraint.color) || 0) < constraint.count
+                ) {
+                    return false;
+                }
+                break;
+
+            case "maxColorCells":
+                if (
+                    (colorCells.get(constraint.color) || 0) > constraint.count
+                ) {
+                    return false;
+                }
+                break;
+
+            case "colorsNotAdjacent": {
+                const [a, b] = constraint.colors;
+                if (adjacentColors.has(`${a}:${b}`)) {
+                    return false;
+                }
+                break;
+            }
+
+            case "adjacent": {
+                const [a, b] = constraint.requirements;
+                if (!adjacentRequirements.has(`${a}:${b}`)) {
+                    return false;
+                }
+                break;
+            }
+
+            case "commandLineContiguous": {
+                // Filled cells on the command line must form a single run, with no empty or forbidden cells in between.
+                const row = array2d.row(grid.cells, grid.commandLineRow);
+                const first = row.findIndex((cell) => cell >= 0);
+                if (first === -1) {
+                    break;
+                }
+                let last = row.length - 1;
+                while (row[last] < 0) {
+                    --last;
+                }
+                for (let x = first; x <= last; ++x) {
+                    if (row[x] < 0) {
+                        return false;
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    return true;
+}
+
+function solidCellsAreConnected(
     parts: Part[],
     requirements: Requirement[],
     grid: Grid
 ) {
-    const placementDetails: PlacementDetail[] = new Array(requirements.length);
-    for (let i = 0; i < requirements.length; ++i) {
-        placementDetails[i] = {
-            outOfBounds: false,
-            onCommandLine: false,
-            adjacentSameColoredPlacements: new Set(),
-        };
+    const isSolidCell = (i: number) =>
+        grid.cells[i] >= 0 &&
+        parts[requirements[grid.cells[i]].partIndex].isSolid;
+
+    const start = grid.cells.findIndex((_, i) => isSolidCell(i));
+    if (start === -1) {
+        return true;
     }
 
-    for (let y = 0; y < grid.cells.nrows; ++y) {
-        for (let x = 0; x < grid.cells.ncols; ++x) {
-            const reqIdx = grid.cells[y * grid.cells.ncols + x];
-            if (reqIdx < 0) {
+    // Flood fill from any solid cell: if it doesn't reach every solid cell, they're split up.
+    const seen = array2d.full(false, grid.cells.nrows, grid.cells.ncols);
+    seen[start] = true;
+    const stack = [start];
+    while (stack.length > 0) {
+        const i = stack.pop()!;
+        const x = i % grid.cells.ncols;
+        const y = Math.floor(i / grid.cells.ncols);
+        for (const [x2, y2] of [
+            [x - 1, y],
+            [x + 1, y],
+            [x, y - 1],
+            [x, y + 1],
+        ]) {
+            if (
+                x2 < 0 ||
+                x2 >= grid.cells.ncols ||
+                y2 < 0 ||
+                y2 >= grid.cells.nrows
+            ) {
+                continue;
+            }
+            const i2 = y2 * grid.cells.ncols + x2;
+            if (seen[i2] || !isSolidCell(i2)) {
                 continue;
             }
-            const req = requirements[reqIdx];
-            const part = parts[req.partIndex];
+            seen[i2] = true;
+            stack.push(i2);
+        }
+    }
+
+    for (let i = 0; i < grid.cells.length; ++i) {
+        if (isSolidCell(i) && !seen[i]) {
+            return false;
+        }
+    }
+    return true;
+}
+
+function solutionIsAdmissible(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    grid: Grid
+) {
+    if (
+        gridSettings.requireSolidConnected &&
+        !solidCellsAreConnected(parts, requirements, grid)
+    ) {
+        return false;
+    }
+
+    const { placementDetails, boardDetail } = resolvePlacementDetails(
+        parts,
+        requirements,
+        grid
+    );
+
+    if (!boardIsAdmissible(gridSettings, grid, boardDetail)) {
+        return false;
+    }
+
+    for (let i = 0; i < placementDetails.length; ++i) {
+        const placementDetail = placementDetails[i];
+        // Left out optional blocks have no bugs to speak of.
+        if (!placementDetail.placed) {
+            continue;
+        }
+
+        const req = requirements[i];
+        const part = parts[req.partIndex];
+
+        const bugLevel = bugLevelForPlacementDetail(
+            placementDetail,
+            part.isSolid
+        );
+
+        if (
+            bugLevel > req.constraint.maxBugLevel ||
+            bugLevel < req.constraint.minBugLevel
+        ) {
+            return false;
+        }
+    }
+
+    return true;
+}
+
+function placementIsAdmissible(
+    grid: Grid,
+    part: Part,
+    reqIdx: number,
+    constraint: Constraint
+) {
+    // Mandatory admissibility: ensure not everything is out of bounds.
+    if (grid.hasOob) {
+        let isAllOob = true;
+        top: for (let y = 1; y < grid.cells.nrows - 1; ++y) {
+            for (let x = 1; x < grid.cells.ncols - 1; ++x) {
+                const cell = grid.get(x, y);
+                if (cell === reqIdx) {
+                    isAllOob = false;
+                    break top;
+                }
+            }
+        }
+        if (isAllOob) {
+            return false;
+        }
+    }
+
+    // Optional admissibility: check if the block is appropriately in/out of bounds.
+    const outOfBounds =
+        grid.hasOob &&
+        (arrayCountNumber(array2d.row(grid.cells, 0), reqIdx) > 0 ||
+            arrayCountNumber(array2d.col(grid.cells, 0), reqIdx) > 0 ||
+            arrayCountNumber(
+                array2d.row(grid.cells, grid.cells.nrows - 1),
+                reqIdx
+            ) > 0 ||
+            arrayCountNumber(
+                array2d.col(grid.cells, grid.cells.ncols - 1),
+                reqIdx
+            ) > 0);
+
+    // Optional admissibility: check if the block is appropriately on/off the command line.
+    const placedOnCommandLine =
+        arrayCountNumber(array2d.row(grid.cells, grid.commandLineRow), reqIdx) >
+        0;
+
+    if (constraint.onCommandLine && !placedOnCommandLine) {
+        return false;
+    }
+
+    // Optional admissibility: check if the block stays within the region its color is restricted to, if any.
+    const colorRegion = grid.colorRegions[part.color];
+    if (colorRegion != null) {
+        for (let i = 0; i < grid.cells.length; ++i) {
+            if (grid.cells[i] === reqIdx && !colorRegion[i]) {
+                return false;
+            }
+        }
+    }
+
+    // Optional admissibility: check if the block is appropriately touching/not touching the edge of the grid.
+    if (constraint.touchesEdge != null) {
+        const touchesEdge =
+            arrayCountNumber(array2d.row(grid.cells, 0), reqIdx) > 0 ||
+            arrayCountNumber(array2d.col(grid.cells, 0), reqIdx) > 0 ||
+            arrayCountNumber(
+                array2d.row(grid.cells, grid.cells.nrows - 1),
+                reqIdx
+            ) > 0 ||
+            arrayCountNumber(
+                array2d.col(grid.cells, grid.cells.ncols - 1),
+                reqIdx
+            ) > 0;
+        if (touchesEdge !== constraint.touchesEdge) {
+            return false;
+        }
+    }
+
+    // It is not possible to tell if the bug level is less than the minimum bug level, because we might see more bugs later due to adjacent colors.
+    // So here, we only check if we have too many bugs.
+    const bugLevel = +outOfBounds + +(part.isSolid === !placedOnCommandLine);
+    if (bugLevel > constraint.maxBugLevel) {
+        return false;
+    }
 
-            const placementDetail = placementDetails[reqIdx];
+    return true;
+}
 
-            // Optional admissibility: check if a block has/doesn't have any out of bounds parts.
+function candidatesForPart(
+    part: Part,
+    orientations: PartOrientations,
+    gridSettings: GridSettings,
+    constraint: Constraint,
+    spinnable: boolean
+): Candidate[] {
+    const candidates: Candidate[] = [];
+    for (const { orientations: maskOrientations, compressed } of allowedForms(
+        part,
+        orientations,
+        constraint
+    )) {
+        for (const { loc, mask } of placementLocationsAndMasksForMask(
+            allowedOrientations(
+                maskOrientations,
+                spinnable,
+                constraint.rotations != null ? constraint.rotations : null
+            ),
+            part,
+            gridSettings,
+            constraint
+        )) {
             if (
-                grid.hasOob &&
-                (x === 0 ||
-                    y === 0 ||
-                    x === grid.cells.ncols - 1 ||
-                    y === grid.cells.nrows - 1)
+                !compressionFitsCommandLine(
+                    constraint,
+                    compressed,
+                    mask,
+                    loc.position,
+                    gridSettings.commandLineRow
+                )
             ) {
-                placementDetail.outOfBounds = true;
+                continue;
+            }
+            candidates.push({ placement: { loc, compressed }, mask });
+        }
+    }
+    return candidates;
+}
+
+// The forms of the part the constraint allows, with their orientations.
+//
+// A pinned form always applies. compressedIfOnCommandLine then only narrows down the placements of the forms that are left, so both have to hold.
+function allowedForms(
+    part: Part,
+    orientations: PartOrientations,
+    constraint: Constraint
+): { orientations: Orientation[]; compressed: boolean }[] {
+    if (constraint.compressed === true) {
+        return [{ orientations: orientations.compressed, compressed: true }];
+    }
+    if (constraint.compressed === false) {
+        return [{ orientations: orientations.uncompressed, compressed: false }];
+    }
+    if (
+        constraint.compressedIfOnCommandLine == null &&
+        array2d.equal(part.compressedMask, part.uncompressedMask)
+    ) {
+        return [{ orientations: orientations.compressed, compressed: true }];
+    }
+    return [
+        { orientations: orientations.compressed, compressed: true },
+        { orientations: orientations.uncompressed, compressed: false },
+    ];
+}
+
+// Conditional admissibility: with compressedIfOnCommandLine, the compressed form is decided by whether the block ended up on the command line.
+function compressionFitsCommandLine(
+    constraint: Constraint,
+    compressed: boolean,
+    mask: array2d.Array2D<boolean>,
+    pos: Position,
+    commandLineRow: number
+) {
+    if (constraint.compressedIfOnCommandLine == null) {
+        return true;
+    }
+    return (
+        compressed ===
+        (constraint.compressedIfOnCommandLine ===
+            maskIsOnCommandLine(mask, pos, commandLineRow))
+    );
+}
+
+function allowedOrientations(
+    orientations: Orientation[],
+    spinnable: boolean,
+    rotations: number[] | null
+): Orientation[] {
+    // Distinct orientations repeat with a period of their count, so e.g. rotation 2 of a part with 2 distinct orientations is the same as rotation 0.
+    const allowed =
+        rotations === null
+            ? orientations
+            : orientations.filter(({ rotation }) =>
+                  rotations.some((r) => r % orientations.length === rotation)
+              );
+
+    // Blocks that can't spin only ever go in unrotated, and only if the constraint allows that.
+    if (!spinnable) {
+        return allowed.filter(({ rotation }) => rotation === 0);
+    }
+    return allowed;
+}
+
+function maskIsOnCommandLine(
+    mask: array2d.Array2D<boolean>,
+    pos: Position,
+    commandLineRow: number
+) {
+    const y = commandLineRow - pos.y;
+    if (y < 0 || y >= mask.nrows) {
+        return false;
+    }
+    return arrayAny(array2d.row(mask, y));
+}
+
+function placementLocationsAndMasksForMask(
+    orientations: Orientation[],
+    part: Part,
+    gridSettings: GridSettings,
+    constraint: Constraint
+) {
+    const locations: { loc: Location; mask: array2d.Array2D<boolean> }[] = [];
+    for (const { rotation, mask } of orientations) {
+        for (const position of placementPositionsForMask(
+            mask,
+            part,
+            gridSettings,
+            constraint
+        )) {
+            locations.push({ loc: { position, rotation }, mask });
+        }
+    }
+    return locations;
+}
+
+function placementPositionsForMask(
+    mask: array2d.Array2D<boolean>,
+    part: Part,
+    gridSettings: GridSettings,
+    constraint: Constraint
+) {
+    const positions: Position[] = [];
+
+    // Positions are where the mask's top left corner goes. Only positions that keep the mask's filled cells on the board can work, so skip the rest before building a grid for them. The extent's bottom and right are exclusive, so the last position that fits puts them just past the board's edge.
+    const extent = maskExtent(mask);
+    const minY = extent != null ? -extent.top : -mask.nrows + 1;
+    const maxY =
+        extent != null
+            ? gridSettings.height - extent.bottom + 1
+            : gridSettings.height;
+    const minX = extent != null ? -extent.left : -mask.ncols + 1;
+    const maxX =
+        extent != null
+            ? gridSettings.width - extent.right + 1
+            : gridSettings.width;
+
+    // Every position is tried on the same grid, which is cleared again after each one.
+    const grid = new Grid(gridSettings);
+    for (let y = minY; y < maxY; ++y) {
+        for (let x = minX; x < maxX; ++x) {
+            const pos = { x, y };
+            if (!grid.canPlace(mask, pos)) {
+                continue;
             }
+            grid.placeNoCheck(mask, pos, 0);
+            const admissible = placementIsAdmissible(grid, part, 0, constraint);
+            grid.reset();
 
-            // Optional admissibility: check if a block is/isn't on the command line.
-            if (y === grid.commandLineRow) {
-                placementDetail.onCommandLine = true;
+            if (!admissible) {
+                continue;
             }
 
-            // Optional admissibility: check if same-colored blocks are appropriately touching/not touching.
-            for (const [x2, y2] of [
-                [x - 1, y],
-                [x + 1, y],
-                [x, y - 1],
-                [x, y + 1],
-            ]) {
-                if (
-                    x2 < 0 ||
-                    x2 >= grid.cells.ncols ||
-                    y2 < 0 ||
-                    y2 >= grid.cells.nrows
-                ) {
-                    continue;
-                }
+            positions.push(pos);
+        }
+    }
 
-                // Ignore touching in out of bounds regions.
-                if (
-                    grid.hasOob &&
-                    (x == 0 ||
-                        y == 0 ||
-                        x == grid.cells.ncols - 1 ||
-                        y == grid.cells.nrows - 1) &&
-                    (x2 == 0 ||
-                        y2 == 0 ||
-                        x2 == grid.cells.ncols - 1 ||
-                        y2 == grid.cells.nrows - 1)
-                ) {
-                    continue;
+    sortPositions(positions, mask, gridSettings);
+
+    return positions;
+}
+
+// The rows and columns spanned by a mask's filled cells, with bottom and right exclusive, or null if the mask is empty.
+function maskExtent(mask: array2d.Array2D<boolean>) {
+    let top = Infinity;
+    let left = Infinity;
+    let bottom = -Infinity;
+    let right = -Infinity;
+    for (let y = 0; y < mask.nrows; ++y) {
+        for (let x = 0; x < mask.ncols; ++x) {
+            if (!mask[y * mask.ncols + x]) {
+                continue;
+            }
+            top = Math.min(top, y);
+            left = Math.min(left, x);
+            bottom = Math.max(bottom, y + 1);
+            right = Math.max(right, x + 1);
+        }
+    }
+    if (top === Infinity) {
+        return null;
+    }
+    return { top, left, bottom, right };
+}
+
+function sortPositions(
+    positions: Position[],
+    mask: array2d.Array2D<boolean>,
+    gridSettings: GridSettings
+) {
+    switch (gridSettings.positionOrder) {
+        case PositionOrder.ColumnMajor: {
+            positions.sort((a, b) => {
+                const cmp = a.x - b.x;
+                if (cmp != 0) {
+                    return cmp;
                 }
+                return a.y - b.y;
+            });
+            break;
+        }
 
-                const neigborReqIdx = grid.cells[y2 * grid.cells.ncols + x2];
-                if (neigborReqIdx < 0) {
-                    continue;
+        case PositionOrder.CenterOut: {
+            // Positions are offsets of the whole mask, so measure from the center of the cells the mask actually occupies.
+            let maskCenterX = 0;
+            let maskCenterY = 0;
+            let n = 0;
+            for (let y = 0; y < mask.nrows; ++y) {
+                for (let x = 0; x < mask.ncols; ++x) {
+                    if (!mask[y * mask.ncols + x]) {
+                        continue;
+                    }
+                    maskCenterX += x;
+                    maskCenterY += y;
+                    ++n;
                 }
+            }
+            if (n > 0) {
+                maskCenterX /= n;
+                maskCenterY /= n;
+            }
 
-                const neigborReq = requirements[neigborReqIdx];
-                const neighborPart = parts[neigborReq.partIndex];
+            const gridCenterX = (gridSettings.width - 1) / 2;
+            const gridCenterY = (gridSettings.height - 1) / 2;
+            const distance = (pos: Position) =>
+                (pos.x + maskCenterX - gridCenterX) ** 2 +
+                (pos.y + maskCenterY - gridCenterY) ** 2;
 
-                if (
-                    neigborReqIdx != reqIdx &&
-                    neighborPart.color === part.color
-                ) {
-                    placementDetail.adjacentSameColoredPlacements.add(
-                        neigborReqIdx
-                    );
-                    break;
-                }
-            }
+            // Array.prototype.sort is stable, so ties stay in row-major order.
+            positions.sort((a, b) => distance(a) - distance(b));
+            break;
         }
     }
+}
 
-    return placementDetails;
+function placementMask(
+    part: Part,
+    placement: Placement
+): array2d.Array2D<boolean> {
+    let mask = placement.compressed
+        ? part.compressedMask
+        : part.uncompressedMask;
+    for (let i = 0; i < placement.loc.rotation; ++i) {
+        mask = array2d.rot90(mask);
+    }
+    return mask;
 }
 
-function solutionIsAdmissible(
-    parts: Part[],
-    requirements: Requirement[],
-    grid: Grid
-) {
-    const placementDetails = resolvePlacementDetails(parts, requirements, grid);
+// Picks one canonical placement among all the placements of the part that cover exactly the same cells, e.g. other rotations of a symmetric part, or the other compression form when both forms have the same shape. Normalized placements compare equal exactly when they cover the same cells.
+export function normalizePlacement(
+    part: Part,
+    placement: Placement
+): Placement {
+    return (
+        placementForMask(
+            part,
+            placementMask(part, placement),
+            placement.loc.position
+        ) || placement
+    );
+}
 
-    for (let i = 0; i < placementDetails.length; ++i) {
-        const placementDetail = placementDetails[i];
-        const req = requirements[i];
-        const part = parts[req.partIndex];
+// Checks whether a single block could go at the placement on an otherwise empty grid, e.g. for feedback while dragging a block around. This accepts exactly the placements the solver could pick for the block.
+export function checkPlacement(
+    part: Part,
+    placement: Placement,
+    gridSettings: GridSettings,
+    constraint: Constraint,
+    spinnable: boolean
+): boolean {
+    const form = allowedForms(part, orientationsForPart(part), constraint).find(
+        ({ compressed }) => compressed === placement.compressed
+    );
+    if (form === undefined) {
+        return false;
+    }
+
+    // Distinct orientations repeat with a period of their count, so only the rotation within that period matters.
+    const rotation = placement.loc.rotation % form.orientations.length;
+    if (
+        !allowedOrientations(
+            form.orientations,
+            spinnable,
+            constraint.rotations != null ? constraint.rotations : null
+        ).some((orientation) => orientation.rotation === rotation)
+    ) {
+        return false;
+    }
+
+    const mask = placementMask(part, placement);
+    if (
+        !compressionFitsCommandLine(
+            constraint,
+            placement.compressed,
+            mask,
+            placement.loc.position,
+            gridSettings.commandLineRow
+        )
+    ) {
+        return false;
+    }
+
+    const grid = new Grid(gridSettings);
+    if (!grid.canPlace(mask, placement.loc.position)) {
+        return false;
+    }
+    grid.placeNoCheck(mask, placement.loc.position, 0);
 
-        const bugLevel =
-            +placementDetail.outOfBounds +
-            +(part.isSolid === !placementDetail.onCommandLine) +
-            placementDetail.adjacentSameColoredPlacements.size;
+    return placementIsAdmissible(grid, part, 0, constraint);
+}
 
+// For each placement in a solution, whether the block could also take its other compression form at the same location with the rest of the solution unchanged. Blocks whose constraint pins compression, either outright or on the command line, and left out optional blocks are never flexible.
+export function compressionFlexibility(
+    parts: Part[],
+    requirements: Requirement[],
+    solution: Solution,
+    gridSettings: GridSettings
+): boolean[] {
+    return solution.map((placement, i) => {
+        const req = requirements[i];
         if (
-            bugLevel > req.constraint.maxBugLevel ||
-            bugLevel < req.constraint.minBugLevel
+            placement === null ||
+            req.constraint.compressed != null ||
+            req.constraint.compressedIfOnCommandLine != null
         ) {
             return false;
         }
+
+        const part = parts[req.partIndex];
+        const other = { ...placement, compressed: !placement.compressed };
+        const mask = placementMask(part, other);
+
+        const rest = solution.slice();
+        rest[i] = null;
+        const grid = Grid.fromSolution(parts, requirements, rest, gridSettings);
+        if (!grid.canPlace(mask, other.loc.position)) {
+            return false;
+        }
+        grid.placeNoCheck(mask, other.loc.position, i);
+
+        return (
+            placementIsAdmissible(grid, part, i, req.constraint) &&
+            solutionIsAdmissible(parts, requirements, gridSettings, grid)
+        );
+    });
+}
+
+// The cells occupied by any placement in a solution, as a board-sized mask.
+export function solutionCoverageMask(
+    parts: Part[],
+    requirements: Requirement[],
+    solution: Solution,
+    gridSettings: GridSettings
+): array2d.Array2D<boolean> {
+    const grid = Grid.fromSolution(parts, requirements, solution, gridSettings);
+    return array2d.from(
+        Array.from(grid.cells, (cell) => cell >= 0),
+        grid.cells.nrows,
+        grid.cells.ncols
+    );
+}
+
+export function placeAll(
+    parts: Part[],
+    requirements: Requirement[],
+    solution: Solution,
+    gridSettings: GridSettings
+): (number | null)[] {
+    const grid = Grid.fromSolution(parts, requirements, solution, gridSettings);
+    const cells = new Array(grid.cells.length);
+
+    for (let i = 0; i < grid.cells.length; ++i) {
+        cells[i] = grid.cells[i] < 0 ? null : grid.cells[i];
     }
+    return cells;
+}
 
-    return true;
+export enum ImportError {
+    MalformedGrid = "malformedGrid",
+    UnknownPart = "unknownPart",
+    UnmatchedRegion = "unmatchedRegion",
 }
 
-function placementIsAdmissible(
-    grid: Grid,
-    isSolid: boolean,
-    reqIdx: number,
-    onCommandLine: boolean | null,
-    maxBugLevel: number
-) {
-    // Mandatory admissibility: ensure not everything is out of bounds.
-    if (grid.hasOob) {
-        let isAllOob = true;
-        top: for (let y = 1; y < grid.cells.nrows - 1; ++y) {
-            for (let x = 1; x < grid.cells.ncols - 1; ++x) {
-                const cell = grid.cells[y * grid.cells.ncols + x];
-                if (cell === reqIdx) {
-                    isAllOob = false;
-                    break top;
-                }
-            }
+export type ImportResult =
+    | { ok: true; partIndexes: number[]; solution: Solution }
+    | { ok: false; error: ImportError };
+
+// Reconstructs a solution from a rendered grid, e.g. a dump of an existing build.
+//
+// The grid has one line per row and whitespace-separated cells per line: a part index for an occupied cell, and `.` or `#` for an empty or forbidden cell. Every 4-connected region of the same part index is taken to be one placed block, so two blocks of the same part must not touch.
+export function importGrid(
+    ascii: string,
+    parts: Part[],
+    gridSettings: GridSettings
+): ImportResult {
+    const lines = ascii
+        .split("\n")
+        .map((line) => line.trim())
+        .filter((line) => line != "");
+    if (lines.length != gridSettings.height) {
+        return { ok: false, error: ImportError.MalformedGrid };
+    }
+
+    const cells = array2d.full(-1, gridSettings.height, gridSettings.width);
+    for (let y = 0; y < lines.length; ++y) {
+        const tokens = lines[y].split(/\s+/);
+        if (tokens.length != gridSettings.width) {
+            return { ok: false, error: ImportError.MalformedGrid };
         }
-        if (isAllOob) {
-            return false;
+        for (let x = 0; x < tokens.length; ++x) {
+            const token = tokens[x];
+            if (token == "." || token == "#") {
+                continue;
+            }
+            if (!/^\d+$/.test(token)) {
+                return { ok: false, error: ImportError.MalformedGrid };
+            }
+            const partIndex = parseInt(token, 10);
+            if (partIndex >= parts.length) {
+                return { ok: false, error: ImportError.UnknownPart };
+            }
+            cells[y * cells.ncols + x] = partIndex;
         }
     }
 
-    // Optional admissibility: check if the block is appropriately in/out of bounds.
-    const outOfBounds =
-        grid.hasOob &&
-        (arrayCountNumber(array2d.row(grid.cells, 0), reqIdx) > 0 ||
-            arrayCountNumber(array2d.col(grid.cells, 0), reqIdx) > 0 ||
-            arrayCountNumber(
-                array2d.row(grid.cells, grid.cells.nrows - 1),
-                reqIdx
-            ) > 0 ||
-            arrayCountNumber(
-                array2d.col(grid.cells, grid.cells.ncols - 1),
-                reqIdx
-            ) > 0);
+    const partIndexes: number[] = [];
+    const solution: Solution = [];
+    const seen = array2d.full(false, cells.nrows, cells.ncols);
+    for (let y = 0; y < cells.nrows; ++y) {
+        for (let x = 0; x < cells.ncols; ++x) {
+            const partIndex = cells[y * cells.ncols + x];
+            if (partIndex < 0 || seen[y * cells.ncols + x]) {
+                continue;
+            }
 
-    // Optional admissibility: check if the block is appropriately on/off the command line.
-    const placedOnCommandLine =
-        arrayCountNumber(array2d.row(grid.cells, grid.commandLineRow), reqIdx) >
-        0;
+            // Flood fill the region this cell belongs to.
+            const region = array2d.full(false, cells.nrows, cells.ncols);
+            const stack = [[x, y]];
+            seen[y * cells.ncols + x] = true;
+            while (stack.length > 0) {
+                const [x1, y1] = stack.pop()!;
+                region[y1 * cells.ncols + x1] = true;
+                for (const [x2, y2] of [
+                    [x1 - 1, y1],
+                    [x1 + 1, y1],
+                    [x1, y1 - 1],
+                    [x1, y1 + 1],
+                ]) {
+                    if (
+                        x2 < 0 ||
+                        x2 >= cells.ncols ||
+                        y2 < 0 ||
+                        y2 >= cells.nrows ||
+                        seen[y2 * cells.ncols + x2] ||
+                        cells[y2 * cells.ncols + x2] != partIndex
+                    ) {
+                        continue;
+                    }
+                    seen[y2 * cells.ncols + x2] = true;
+                    stack.push([x2, y2]);
+                }
+            }
 
-    if (onCommandLine && !placedOnCommandLine) {
-        return false;
+            const placement = placementForMask(parts[partIndex], region, {
+                x: 0,
+                y: 0,
+            });
+            if (placement === null) {
+                return { ok: false, error: ImportError.UnmatchedRegion };
+            }
+            partIndexes.push(partIndex);
+            solution.push(placement);
+        }
     }
 
-    // It is not possible to tell if the bug level is less than the minimum bug level, because we might see more bugs later due to adjacent colors.
-    // So here, we only check if we have too many bugs.
-    const bugLevel = +outOfBounds + +(isSolid === !placedOnCommandLine);
-    if (bugLevel > maxBugLevel) {
-        return false;
-    }
+    return { ok: true, partIndexes, solution };
+}
 
-    return true;
+function firstTrueCell(mask: array2d.Array2D<boolean>): Position {
+    const i = mask.indexOf(true);
+    return { x: i % mask.ncols, y: Math.floor(i / mask.ncols) };
 }
 
-function candidatesForPart(
+// The canonical placement of the part that covers exactly the cells of the mask, with the mask's top left corner at origin. Compressed comes before uncompressed, and the first distinct rotation wins. Returns null if no form of the part has the mask's shape.
+function placementForMask(
     part: Part,
-    gridSettings: GridSettings,
-    constraint: Constraint,
-    spinnable: boolean
-): Candidate[] {
-    const candidates: Candidate[] = [];
-    const partMasks =
-        constraint.compressed === false
-            ? [{ mask: part.uncompressedMask, compressed: false }]
-            : constraint.compressed ||
-              array2d.equal(part.compressedMask, part.uncompressedMask)
-            ? [{ mask: part.compressedMask, compressed: true }]
-            : [
-                  { mask: part.compressedMask, compressed: true },
-                  { mask: part.uncompressedMask, compressed: false },
-              ];
-    for (const { mask: partMask, compressed } of partMasks) {
-        for (const { loc, mask } of placementLocationsAndMasksForMask(
-            partMask,
-            part.isSolid,
-            gridSettings,
-            constraint.onCommandLine,
-            constraint.maxBugLevel,
-            spinnable
-        )) {
-            candidates.push({ placement: { loc, compressed }, mask });
+    placed: array2d.Array2D<boolean>,
+    origin: Position
+): Placement | null {
+    const shape = encodeMaskToString(trim(placed));
+    const placedFirst = firstTrueCell(placed);
+
+    for (const { mask: partMask, compressed } of [
+        { mask: part.compressedMask, compressed: true },
+        { mask: part.uncompressedMask, compressed: false },
+    ]) {
+        for (const { rotation, mask } of distinctOrientations(partMask)) {
+            if (encodeMaskToString(trim(mask)) != shape) {
+                continue;
+            }
+
+            // Both masks have the same shape, so their first occupied cells line up.
+            const maskFirst = firstTrueCell(mask);
+            return {
+                loc: {
+                    position: {
+                        x: origin.x + placedFirst.x - maskFirst.x,
+                        y: origin.y + placedFirst.y - maskFirst.y,
+                    },
+                    rotation,
+                },
+                compressed,
+            };
         }
     }
-    return candidates;
+
+    return null;
 }
 
-function placementLocationsAndMasksForMask(
-    mask: array2d.Array2D<boolean>,
-    isSolid: boolean,
+// Finds the topK best solutions according to rankSolution, best first. rankSolution is given each solution's grid of requirement indices, and higher scores are better.
+//
+// Unlike solve, this has to run the search to completion before it can return anything.
+export function solveRanked(
+    parts: Part[],
+    requirements: Requirement[],
     gridSettings: GridSettings,
-    onCommandLine: boolean | null,
-    maxBugLevel: number,
-    spinnable: boolean
-) {
-    const locations: { loc: Location; mask: array2d.Array2D<boolean> }[] = [];
-    for (const position of placementPositionsForMask(
-        mask,
-        isSolid,
+    spinnableColors: boolean[],
+    rankSolution: (cells: (number | null)[]) => number,
+    topK: number
+): Solution[] {
+    // Kept sorted best first, and never longer than topK.
+    const best: { score: number; solution: Solution }[] = [];
+    if (topK <= 0) {
+        return [];
+    }
+
+    for (const solution of solve(
+        parts,
+        requirements,
         gridSettings,
-        onCommandLine,
-        maxBugLevel
+        spinnableColors
     )) {
-        locations.push({ loc: { position, rotation: 0 }, mask });
+        const score = rankSolution(
+            placeAll(parts, requirements, solution, gridSettings)
+        );
+        if (best.length === topK && score <= best[best.length - 1].score) {
+            continue;
+        }
+
+        // Insert after any solutions with the same score, so earlier solutions win ties.
+        let lo = 0;
+        let hi = best.length;
+        while (lo < hi) {
+            const mid = (lo + hi) >> 1;
+            if (best[mid].score >= score) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        best.splice(lo, 0, { score, solution });
+        if (best.length > topK) {
+            best.pop();
+        }
     }
 
-    if (spinnable) {
-        const knownMasks = new Set();
-        knownMasks.add(encodeMaskToString(trim(mask)));
+    return best.map(({ solution }) => solution);
+}
 
-        for (let i = 1; i < 4; ++i) {
-            mask = array2d.rot90(mask);
-            const knownMask = encodeMaskToString(trim(mask));
-            if (knownMasks.has(knownMask)) {
-                break;
-            }
-            knownMasks.add(knownMask);
+function compressedPlacementCount(solution: Solution): number {
+    let n = 0;
+    for (const placement of solution) {
+        if (placement !== null && placement.compressed) {
+            ++n;
+        }
+    }
+    return n;
+}
 
-            for (const position of placementPositionsForMask(
-                mask,
-                isSolid,
-                gridSettings,
-                onCommandLine,
-                maxBugLevel
-            )) {
-                locations.push({
-                    loc: { position, rotation: i },
-                    mask,
-                });
+// Like solve, but returns every solution with the ones that compress the most blocks first. Solutions that compress equally many blocks stay in the order solve finds them in.
+//
+// Each block's candidates are already tried compressed first, but that only decides the order within one branch of the search, so this has to run the search to completion and sort afterwards.
+export function solveCompressedFirst(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    spinnableColors: boolean[]
+): Solution[] {
+    const solutions = Array.from(
+        solve(parts, requirements, gridSettings, spinnableColors),
+        (solution) => ({
+            solution,
+            compressed: compressedPlacementCount(solution),
+        })
+    );
+    solutions.sort((a, b) => b.compressed - a.compressed);
+    return solutions.map(({ solution }) => solution);
+}
+
+function boundingBoxArea(cells: (number | null)[], width: number): number {
+    let left = Infinity;
+    let top = Infinity;
+    let right = -Infinity;
+    let bottom = -Infinity;
+    for (let i = 0; i < cells.length; ++i) {
+        if (cells[i] === null) {
+            continue;
+        }
+        const x = i % width;
+        const y = Math.floor(i / width);
+        left = Math.min(left, x);
+        top = Math.min(top, y);
+        right = Math.max(right, x);
+        bottom = Math.max(bottom, y);
+    }
+    if (left === Infinity) {
+        return 0;
+    }
+    return (right - left + 1) * (bottom - top + 1);
+}
+
+// Finds the solution whose placed blocks fit in the smallest bounding box, or null if there are no solutions.
+export function solveMinimal(
+    parts: Part[],
+    requirements: Requirement[],
+    gridSettings: GridSettings,
+    spinnableColors: boolean[]
+): Solution | null {
+    // No bounding box can be smaller than the cells the required blocks take up, so once a solution hits that there's nothing better to find.
+    let lowerBound = 0;
+    for (const req of requirements) {
+        if (req.optional) {
+            continue;
+        }
+        const part = parts[req.partIndex];
+        if (part === undefined) {
+            return null;
+        }
+        lowerBound += Math.min(partArea(part, true), partArea(part, false));
+    }
+
+    let best: Solution | null = null;
+    let bestArea = Infinity;
+    for (const solution of solve(
+        parts,
+        requirements,
+        gridSettings,
+        spinnableColors
+    )) {
+        const area = boundingBoxArea(
+            placeAll(parts, requirements, solution, gridSettings),
+            gridSettings.width
+        );
+        if (area < bestArea) {
+            best = solution;
+            bestArea = area;
+            if (bestArea <= lowerBound) {
+                break;
             }
         }
     }
+    return best;
+}
 
-    return locations;
+// Identifies the arrangement a solution places, ignoring whether each block is compressed: the position and rotation of every requirement, or "-" for optional requirements that were left out, joined with semicolons.
+export type ArrangementKey = string;
+
+function arrangementKey(solution: Solution): ArrangementKey {
+    return solution
+        .map((placement) =>
+            placement === null
+                ? "-"
+                : `${placement.loc.position.x},${placement.loc.position.y},${placement.loc.rotation}`
+        )
+        .join(";");
 }
 
-function placementPositionsForMask(
-    mask: array2d.Array2D<boolean>,
-    isSolid: boolean,
+// Like solve, but groups solutions that are the same arrangement of blocks and only differ in which blocks are compressed.
+//
+// Solutions for the same arrangement aren't necessarily found one after another, so this runs the whole search before yielding anything. Groups are yielded in the order their first solution was found.
+export function* solveGrouped(
+    parts: Part[],
+    requirements: Requirement[],
     gridSettings: GridSettings,
-    onCommandLine: boolean | null,
-    maxBugLevel: number
-) {
-    const positions: Position[] = [];
+    spinnableColors: boolean[]
+): Iterable<[ArrangementKey, Solution[]]> {
+    const groups = new Map<ArrangementKey, Solution[]>();
+    for (const solution of solve(
+        parts,
+        requirements,
+        gridSettings,
+        spinnableColors
+    )) {
+        const key = arrangementKey(solution);
+        let group = groups.get(key);
+        if (group === undefined) {
+            group = [];
+            groups.set(key, group);
+        }
+        group.push(solution);
+    }
+    yield* groups;
+}
 
-    for (let y = -mask.nrows + 1; y < mask.nrows; ++y) {
-        for (let x = -mask.ncols + 1; x < mask.ncols; ++x) {
-            const pos = { x, y };
-            const grid = new Grid(gridSettings);
-            if (!grid.canPlace(mask, pos)) {
-                continue;
-            }
-            grid.placeNoCheck(mask, pos, 0);
+// Throws instead of letting a value that doesn't fit in a byte be silently truncated.
+function checkByteRange(
+    value: number,
+    min: number,
+    max: number,
+    what: string
+) {
+    if (!Number.isInteger(value) || value < min || value > max) {
+        throw new Error(`${what} out of range: ${value}`);
+    }
+}
 
-            if (
-                !placementIsAdmissible(
-                    grid,
-                    isSolid,
-                    0,
-                    onCommandLine,
-                    maxBugLevel
-                )
-            ) {
-                continue;
+function encodeMaskBits(mask: array2d.Array2D<boolean>, out: number[]) {
+    checkByteRange(mask.nrows, 0, 0xff, "mask rows");
+    checkByteRange(mask.ncols, 0, 0xff, "mask columns");
+    out.push(mask.nrows, mask.ncols);
+    for (let i = 0; i < mask.length; i += 8) {
+        let byte = 0;
+        for (let j = 0; j < 8 && i + j < mask.length; ++j) {
+            if (mask[i + j]) {
+                byte |= 1 << j;
             }
-
-            positions.push(pos);
         }
+        out.push(byte);
     }
+}
 
-    return positions;
+function decodeMaskBits(
+    bytes: Uint8Array,
+    offset: number
+): [array2d.Array2D<boolean>, number] {
+    if (offset + 2 > bytes.length) {
+        throw new Error("truncated mask header");
+    }
+    const nrows = bytes[offset];
+    const ncols = bytes[offset + 1];
+    offset += 2;
+
+    const n = nrows * ncols;
+    const end = offset + Math.ceil(n / 8);
+    if (end > bytes.length) {
+        throw new Error("truncated mask");
+    }
+    const mask = array2d.full(false, nrows, ncols);
+    for (let i = 0; i < n; ++i) {
+        mask[i] = (bytes[offset + (i >> 3)] & (1 << (i & 7))) != 0;
+    }
+    return [mask, end];
 }
 
-export function placeAll(
-    parts: Part[],
-    requirements: Requirement[],
-    placements: Placement[],
-    gridSettings: GridSettings
-): (number | null)[] {
-    const grid = new Grid(gridSettings);
-    const cells = new Array(grid.cells.length);
+// Packs a part into a compact binary form: a flags byte (bit 0 is isSolid), the color, then the compressed and uncompressed masks, each as a rows byte, a columns byte and one bit per cell.
+export function partToBytes(part: Part): Uint8Array {
+    checkByteRange(part.color, 0, 0xff, "color");
+    const out: number[] = [+part.isSolid, part.color];
+    encodeMaskBits(part.compressedMask, out);
+    encodeMaskBits(part.uncompressedMask, out);
+    return Uint8Array.from(out);
+}
 
-    for (let i = 0; i < placements.length; ++i) {
-        const req = requirements[i];
-        const placement = placements[i];
-        const part = parts[req.partIndex];
-        let mask = placement.compressed
-            ? part.compressedMask
-            : part.uncompressedMask;
-        for (let j = 0; j < placement.loc.rotation; ++j) {
-            mask = array2d.rot90(mask);
+// The inverse of partToBytes.
+export function partFromBytes(bytes: Uint8Array): Part {
+    if (bytes.length < 2) {
+        throw new Error("truncated part header");
+    }
+    const isSolid = (bytes[0] & 1) != 0;
+    const color = bytes[1];
+    const [compressedMask, offset] = decodeMaskBits(bytes, 2);
+    const [uncompressedMask] = decodeMaskBits(bytes, offset);
+    return { isSolid, color, compressedMask, uncompressedMask };
+}
+
+// Packs a solution into a compact binary form for sharing, e.g. as a build code. Each requirement in order takes one byte if it was left out, or three if placed: a header byte (bit 0 set, bit 1 compressed, bits 2-3 rotation), then x and y as signed bytes.
+export function solutionToBytes(solution: Solution): Uint8Array {
+    const out: number[] = [];
+    for (const placement of solution) {
+        if (placement === null) {
+            out.push(0);
+            continue;
         }
-        grid.placeNoCheck(mask, placement.loc.position, i);
+        checkByteRange(placement.loc.rotation, 0, 3, "rotation");
+        checkByteRange(placement.loc.position.x, -0x80, 0x7f, "x");
+        checkByteRange(placement.loc.position.y, -0x80, 0x7f, "y");
+        out.push(
+            1 |
+                (+placement.compressed << 1) |
+                (placement.loc.rotation << 2),
+            placement.loc.position.x & 0xff,
+            placement.loc.position.y & 0xff
+        );
     }
+    return Uint8Array.from(out);
+}
 
-    for (let i = 0; i < grid.cells.length; ++i) {
-        cells[i] = grid.cells[i] < 0 ? null : grid.cells[i];
+// The inverse of solutionToBytes.
+export function solutionFromBytes(bytes: Uint8Array): Solution {
+    const solution: Solution = [];
+    for (let i = 0; i < bytes.length; ) {
+        const header = bytes[i++];
+        if ((header & 1) == 0) {
+            solution.push(null);
+            continue;
+        }
+        if (i + 2 > bytes.length) {
+            throw new Error("truncated placement");
+        }
+        // Sign-extend the coordinates, since blocks can hang off the top and left of the grid.
+        const x = (bytes[i++] << 24) >> 24;
+        const y = (bytes[i++] << 24) >> 24;
+        solution.push({
+            loc: { position: { x, y }, rotation: (header >> 2) & 3 },
+            compressed: (header & 2) != 0,
+        });
     }
-    return cells;
+    return solution;
 }